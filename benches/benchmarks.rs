@@ -108,7 +108,7 @@ pub fn cache_metadata_lookup(c: &mut Criterion) {
         rt.block_on(db.write(&KEY, &value)).unwrap();
 
         b.iter(|| {
-            db.read_metadata(&KEY).unwrap();
+            db.read_metadata(KEY).unwrap();
         });
     });
 }
@@ -0,0 +1,306 @@
+use crate::Result;
+use std::fmt;
+use std::path;
+use std::sync::Arc;
+
+mod memory;
+#[cfg(feature = "redb")]
+mod redb_db;
+mod sled_db;
+
+pub use memory::MemoryBackend;
+#[cfg(feature = "redb")]
+pub use redb_db::RedbBackend;
+pub use sled_db::SledBackend;
+
+/// A separate, ordered collection of keys inside a [`MetaBackend`].
+///
+/// The metadata database stores entry metadata and a few secondary indexes side-by-side, each in
+/// their own keyspace. A backend can map these onto whatever it has available (trees, tables,
+/// column families, or just key prefixes), as long as every keyspace is kept separate.
+///
+/// More keyspaces may be added in future versions, so backends should create their storage from
+/// [`Keyspace::ALL`] instead of matching on the variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Keyspace {
+    /// The serialized [`Metadata`](crate::Metadata) of every entry, keyed by the entry key
+    Entries,
+    /// The index of tagged entries
+    Tags,
+    /// Information about the database itself, such as the format version
+    Info,
+    /// The index of entries ordered by their last access time
+    AccessTime,
+    /// The index of entries ordered by their last modification time
+    ModifiedTime,
+    /// Operations that are in progress, used to recover from crashes
+    Journal,
+}
+
+impl Keyspace {
+    /// Every keyspace, in order of [`index`](Self::index)
+    pub const ALL: &'static [Keyspace] = &[
+        Self::Entries,
+        Self::Tags,
+        Self::Info,
+        Self::AccessTime,
+        Self::ModifiedTime,
+        Self::Journal,
+    ];
+
+    /// A unique and stable name for the keyspace, usable as a table or tree name.
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Entries => "entries",
+            Self::Tags => "tags",
+            Self::Info => "info",
+            Self::AccessTime => "by_accessed",
+            Self::ModifiedTime => "by_modified",
+            Self::Journal => "journal",
+        }
+    }
+
+    /// The position of this keyspace in [`Keyspace::ALL`]
+    #[inline]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A single operation of a [`Batch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Inserts (or replaces) `key` in `keyspace` with `value`
+    Insert {
+        /// The keyspace to insert into
+        keyspace: Keyspace,
+        /// The key to insert
+        key: Vec<u8>,
+        /// The value to insert
+        value: Vec<u8>,
+    },
+    /// Removes `key` from `keyspace`, if it exists
+    Remove {
+        /// The keyspace to remove from
+        keyspace: Keyspace,
+        /// The key to remove
+        key: Vec<u8>,
+    },
+}
+
+/// A list of operations that a [`MetaBackend`] must apply atomically.
+///
+/// Either every operation in the batch is applied, or none of them are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    /// Creates a new, empty [`Batch`]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an insert of `key` with `value` into `keyspace` to the batch
+    pub fn insert<K: Into<Vec<u8>>, V: Into<Vec<u8>>>(
+        &mut self,
+        keyspace: Keyspace,
+        key: K,
+        value: V,
+    ) {
+        self.ops.push(BatchOp::Insert {
+            keyspace,
+            key: key.into(),
+            value: value.into(),
+        });
+    }
+
+    /// Adds a removal of `key` from `keyspace` to the batch
+    pub fn remove<K: Into<Vec<u8>>>(&mut self, keyspace: Keyspace, key: K) {
+        self.ops.push(BatchOp::Remove {
+            keyspace,
+            key: key.into(),
+        });
+    }
+
+    /// The operations in this batch, in the order they should be applied
+    #[inline]
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// Whether the batch has no operations
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// An iterator over key-value pairs of a [`Keyspace`], in ascending key order.
+pub type BackendIter = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + Send>;
+
+/// A storage backend for the metadata database of a [`Cache`](crate::Cache).
+///
+/// Backends are ordered key-value stores split into several [`Keyspace`]s. All of the logic for
+/// (de)serializing [`Metadata`](crate::Metadata), tracking access, and keeping secondary indexes
+/// consistent lives above this trait, so implementations only have to provide basic storage and
+/// atomic batches.
+///
+/// # Implementations
+///
+/// * [`SledBackend`] - stores metadata in a [`sled`] database (**default**)
+/// * [`MemoryBackend`] - stores metadata in memory, nothing is persisted
+/// * `RedbBackend` - stores metadata in a [`redb`](https://docs.rs/redb) database (requires
+///   the `redb` feature)
+///
+/// The backend is selected with
+/// [`CacheBuilder::meta_backend`](crate::CacheBuilder::meta_backend).
+pub trait MetaBackend: fmt::Debug + Send + Sync {
+    /// Retrieves the value of `key` in `keyspace`.
+    fn get(&self, keyspace: Keyspace, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Iterates over every key-value pair in `keyspace`, in ascending key order.
+    fn iter(&self, keyspace: Keyspace) -> BackendIter;
+
+    /// Iterates over every key-value pair in `keyspace` whose key starts with `prefix`, in
+    /// ascending key order.
+    fn scan_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> BackendIter;
+
+    /// Applies every operation in `batch` atomically.
+    fn apply_batch(&self, batch: Batch) -> Result<()>;
+
+    /// Inserts (or replaces) `key` in `keyspace` with `value`.
+    fn insert(&self, keyspace: Keyspace, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = Batch::new();
+        batch.insert(keyspace, key, value);
+        self.apply_batch(batch)
+    }
+
+    /// Removes `key` from `keyspace`, if it exists.
+    fn remove(&self, keyspace: Keyspace, key: &[u8]) -> Result<()> {
+        let mut batch = Batch::new();
+        batch.remove(keyspace, key);
+        self.apply_batch(batch)
+    }
+
+    /// Makes sure every applied batch is persisted to disk, blocking until it is.
+    ///
+    /// The default implementation does nothing, which is correct for backends that persist every
+    /// batch before [`apply_batch`](Self::apply_batch) returns (or don't persist anything).
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether `keyspace` contains no keys.
+    fn is_empty(&self, keyspace: Keyspace) -> Result<bool> {
+        self.iter(keyspace).next().transpose().map(|x| x.is_none())
+    }
+}
+
+/// Name of the directory in the cache that the sled backend is stored in
+const SLED_PATH: &str = "index";
+/// Name of the file in the cache that the redb backend is stored in
+#[cfg(feature = "redb")]
+const REDB_PATH: &str = "index.redb";
+
+/// Selects which [`MetaBackend`] a [`Cache`](crate::Cache) stores its metadata in.
+///
+/// See [`CacheBuilder::meta_backend`](crate::CacheBuilder::meta_backend).
+#[derive(Debug, Clone, Default)]
+pub enum MetaBackendKind {
+    /// A [`SledBackend`] stored in the `index` directory of the cache
+    #[default]
+    Sled,
+    /// A [`MemoryBackend`], which means metadata is lost when the cache is dropped
+    Memory,
+    /// A `RedbBackend` stored in the `index.redb` file of the cache
+    #[cfg(feature = "redb")]
+    Redb,
+    /// A user-provided backend
+    Custom(Arc<dyn MetaBackend>),
+}
+
+impl MetaBackendKind {
+    /// Opens the selected backend for the cache in the directory `root`.
+    pub(crate) fn open(&self, root: &path::Path) -> Result<Arc<dyn MetaBackend>> {
+        Ok(match self {
+            Self::Sled => Arc::new(SledBackend::open(root.join(SLED_PATH))?),
+            Self::Memory => Arc::new(MemoryBackend::new()),
+            #[cfg(feature = "redb")]
+            Self::Redb => Arc::new(RedbBackend::open(root.join(REDB_PATH))?),
+            Self::Custom(backend) => Arc::clone(backend),
+        })
+    }
+
+    /// Opens the selected backend for the cache in the directory `root` in its read-only mode,
+    /// or returns `None` if it doesn't have one, or it's currently opened for writing.
+    #[cfg_attr(not(feature = "redb"), allow(unused_variables))]
+    pub(crate) fn open_read_only(&self, root: &path::Path) -> Result<Option<Arc<dyn MetaBackend>>> {
+        match self {
+            #[cfg(feature = "redb")]
+            Self::Redb => match RedbBackend::open_read_only(root.join(REDB_PATH)) {
+                Ok(backend) => Ok(Some(Arc::new(backend))),
+                Err(e) if RedbBackend::is_already_open(&e) => Ok(None),
+                Err(e) => Err(e),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// The file or directory that the selected backend stores its data in, for the cache in the
+    /// directory `root`. `None` if the backend isn't stored inside the cache directory.
+    pub(crate) fn storage_path(&self, root: &path::Path) -> Option<path::PathBuf> {
+        match self {
+            Self::Sled => Some(root.join(SLED_PATH)),
+            #[cfg(feature = "redb")]
+            Self::Redb => Some(root.join(REDB_PATH)),
+            Self::Memory | Self::Custom(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs the basic operations every backend must support
+    pub(super) fn exercise(backend: &dyn MetaBackend) {
+        let ks = Keyspace::Tags;
+        backend.insert(ks, b"b", b"2").unwrap();
+        backend.insert(ks, b"a", b"1").unwrap();
+        backend.insert(ks, b"ab", b"3").unwrap();
+        backend.insert(Keyspace::Info, b"a", b"other").unwrap();
+        assert_eq!(backend.get(ks, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(backend.get(ks, b"c").unwrap(), None);
+
+        // iteration must be ordered and keyspaces must be separate
+        let keys: Vec<_> = backend.iter(ks).map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"ab".to_vec(), b"b".to_vec()]);
+        let keys: Vec<_> = backend
+            .scan_prefix(ks, b"a")
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"ab".to_vec()]);
+
+        let mut batch = Batch::new();
+        batch.remove(ks, b"a".as_slice());
+        batch.insert(ks, b"c".as_slice(), b"4".as_slice());
+        batch.remove(Keyspace::Info, b"a".as_slice());
+        backend.apply_batch(batch).unwrap();
+        assert_eq!(backend.get(ks, b"a").unwrap(), None);
+        assert_eq!(backend.get(ks, b"c").unwrap(), Some(b"4".to_vec()));
+        assert!(backend.is_empty(Keyspace::Info).unwrap());
+        assert!(!backend.is_empty(ks).unwrap());
+    }
+
+    #[test]
+    fn keyspace_index() {
+        for (i, ks) in Keyspace::ALL.iter().enumerate() {
+            assert_eq!(ks.index(), i);
+        }
+    }
+}
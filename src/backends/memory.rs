@@ -0,0 +1,184 @@
+use super::{BackendIter, Batch, BatchOp, Keyspace, MetaBackend};
+use crate::Result;
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::sync::Arc;
+
+type Space = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Number of entries an iterator copies out of the map at a time
+const CHUNK_SIZE: usize = 64;
+
+/// A [`MetaBackend`] that keeps all metadata in memory.
+///
+/// Nothing is persisted, so all metadata is lost once the backend is dropped. This is mostly
+/// useful for tests and short-lived caches.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::{Cache, backends::MetaBackendKind};
+///
+/// let cache = Cache::new("./cache")
+///     .meta_backend(MetaBackendKind::Memory)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MemoryBackend {
+    /// The map of every keyspace, indexed by [`Keyspace::index`]
+    spaces: Arc<RwLock<Vec<Space>>>,
+}
+
+impl MemoryBackend {
+    /// Creates a new, empty [`MemoryBackend`]
+    pub fn new() -> Self {
+        Self {
+            spaces: Arc::new(RwLock::new(vec![Space::new(); Keyspace::ALL.len()])),
+        }
+    }
+
+    /// Iterates over the entries of `keyspace` starting with `prefix`
+    fn iter_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> BackendIter {
+        Box::new(PrefixIter {
+            spaces: Arc::clone(&self.spaces),
+            keyspace,
+            prefix: prefix.to_vec(),
+            start: Bound::Included(prefix.to_vec()),
+            chunk: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+/// An iterator over the entries of a keyspace that start with a prefix.
+///
+/// Iterators can't hold on to the lock of the map, so the entries are copied out in small chunks
+/// instead, each one continuing after the last key of the previous one. Like the iterators of
+/// the other backends, changes made while iterating may or may not be seen.
+struct PrefixIter {
+    spaces: Arc<RwLock<Vec<Space>>>,
+    keyspace: Keyspace,
+    prefix: Vec<u8>,
+    /// Where the next chunk starts
+    start: Bound<Vec<u8>>,
+    chunk: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// Whether the last chunk was reached
+    done: bool,
+}
+
+impl Iterator for PrefixIter {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.chunk.is_empty() && !self.done {
+            let guard = self.spaces.read();
+            self.chunk = guard[self.keyspace.index()]
+                .range((self.start.clone(), Bound::Unbounded))
+                .take_while(|(k, _)| k.starts_with(&self.prefix))
+                .take(CHUNK_SIZE)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            self.done = self.chunk.len() < CHUNK_SIZE;
+            if let Some((last, _)) = self.chunk.back() {
+                self.start = Bound::Excluded(last.clone());
+            }
+        }
+        self.chunk.pop_front().map(Ok)
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetaBackend for MemoryBackend {
+    fn get(&self, keyspace: Keyspace, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.spaces.read()[keyspace.index()].get(key).cloned())
+    }
+
+    fn iter(&self, keyspace: Keyspace) -> BackendIter {
+        self.iter_prefix(keyspace, &[])
+    }
+
+    fn scan_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> BackendIter {
+        self.iter_prefix(keyspace, prefix)
+    }
+
+    fn apply_batch(&self, batch: Batch) -> Result<()> {
+        // holding the write lock for the whole batch makes it atomic
+        let mut guard = self.spaces.write();
+        for op in batch.ops {
+            match op {
+                BatchOp::Insert {
+                    keyspace,
+                    key,
+                    value,
+                } => {
+                    guard[keyspace.index()].insert(key, value);
+                }
+                BatchOp::Remove { keyspace, key } => {
+                    guard[keyspace.index()].remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self, keyspace: Keyspace) -> Result<bool> {
+        Ok(self.spaces.read()[keyspace.index()].is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_backend() {
+        crate::backends::test::exercise(&MemoryBackend::new());
+    }
+
+    #[test]
+    fn chunked_iteration() {
+        let backend = MemoryBackend::new();
+        let mut batch = Batch::new();
+        for i in 0..(CHUNK_SIZE as u16 * 3) {
+            batch.insert(
+                Keyspace::Entries,
+                [b"a".as_slice(), &i.to_be_bytes()].concat(),
+                b"",
+            );
+            batch.insert(
+                Keyspace::Entries,
+                [b"b".as_slice(), &i.to_be_bytes()].concat(),
+                b"",
+            );
+        }
+        backend.apply_batch(batch).unwrap();
+
+        let keys = backend
+            .scan_prefix(Keyspace::Entries, b"a")
+            .map(|x| x.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), CHUNK_SIZE * 3);
+        assert!(keys.is_sorted() && keys.iter().all(|k| k.starts_with(b"a")));
+        assert_eq!(backend.iter(Keyspace::Entries).count(), CHUNK_SIZE * 6);
+
+        // entries are copied lazily, so later chunks see changes made in the meantime
+        let mut iter = backend.scan_prefix(Keyspace::Entries, b"b");
+        iter.next().unwrap().unwrap();
+        backend
+            .insert(Keyspace::Entries, b"b\xff\xff\xff", b"")
+            .unwrap();
+        let last = iter.last().unwrap().unwrap().0;
+        assert_eq!(last, b"b\xff\xff\xff");
+    }
+}
@@ -0,0 +1,215 @@
+use super::{BackendIter, Batch, BatchOp, Keyspace, MetaBackend};
+use crate::{ForcepError, Result};
+use redb::{ReadableDatabase, ReadableTableMetadata, TableDefinition};
+use std::path;
+
+type Table = TableDefinition<'static, &'static [u8], &'static [u8]>;
+
+/// The table definition that stores `keyspace`
+#[inline]
+fn table(keyspace: Keyspace) -> Table {
+    TableDefinition::new(keyspace.name())
+}
+
+/// Converts any redb error into a [`ForcepError`]
+#[inline]
+fn map_err<E: Into<redb::Error>>(e: E) -> ForcepError {
+    ForcepError::Backend(Box::new(e.into()))
+}
+
+/// A [`MetaBackend`] that stores metadata in a [`redb`] database file.
+///
+/// Every [`Keyspace`] is stored in its own table. Requires the `redb` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::{Cache, backends::MetaBackendKind};
+///
+/// let cache = Cache::new("./cache")
+///     .meta_backend(MetaBackendKind::Redb)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RedbBackend {
+    db: Db,
+}
+
+/// The database of a [`RedbBackend`], depending on how it was opened
+enum Db {
+    Writable(redb::Database),
+    ReadOnly(redb::ReadOnlyDatabase),
+}
+
+impl std::fmt::Debug for Db {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Writable(db) => f.debug_tuple("Writable").field(db).finish(),
+            Self::ReadOnly(_) => f.write_str("ReadOnly"),
+        }
+    }
+}
+
+impl RedbBackend {
+    /// Opens (or creates) the redb database file at `path`.
+    pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Self> {
+        let db = redb::Database::create(path).map_err(map_err)?;
+
+        // create every table up front, so reads never have to deal with missing tables
+        let txn = db.begin_write().map_err(map_err)?;
+        for ks in Keyspace::ALL {
+            txn.open_table(table(*ks)).map_err(map_err)?;
+        }
+        txn.commit().map_err(map_err)?;
+
+        Ok(Self {
+            db: Db::Writable(db),
+        })
+    }
+
+    /// Opens the existing redb database file at `path` in read-only mode.
+    ///
+    /// Any number of read-only handles to the same file can be open at once (also from other
+    /// processes), but not while it's opened for writing. Every change fails with
+    /// [`ForcepError::ReadOnly`].
+    pub fn open_read_only<P: AsRef<path::Path>>(path: P) -> Result<Self> {
+        let db = redb::ReadOnlyDatabase::open(path).map_err(map_err)?;
+        Ok(Self {
+            db: Db::ReadOnly(db),
+        })
+    }
+
+    /// Whether `e` was returned because the database is already opened for writing
+    pub(crate) fn is_already_open(e: &ForcepError) -> bool {
+        matches!(e, ForcepError::Backend(e) if matches!(
+            e.downcast_ref::<redb::Error>(),
+            Some(redb::Error::DatabaseAlreadyOpen)
+        ))
+    }
+
+    /// Opens a read-only handle to the table for `keyspace`
+    fn read_table(
+        &self,
+        keyspace: Keyspace,
+    ) -> Result<redb::ReadOnlyTable<&'static [u8], &'static [u8]>> {
+        let txn = match &self.db {
+            Db::Writable(db) => db.begin_read(),
+            Db::ReadOnly(db) => db.begin_read(),
+        };
+        txn.map_err(map_err)?
+            .open_table(table(keyspace))
+            .map_err(map_err)
+    }
+
+    /// Iterates over the entries of `keyspace` starting with `prefix`
+    fn range_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> Result<BackendIter> {
+        let range = self
+            .read_table(keyspace)?
+            .range::<&[u8]>(prefix..)
+            .map_err(map_err)?;
+        let prefix = prefix.to_vec();
+        Ok(Box::new(
+            range
+                .map(|x| match x {
+                    Ok((k, v)) => Ok((k.value().to_vec(), v.value().to_vec())),
+                    Err(e) => Err(map_err(e)),
+                })
+                .take_while(move |x| match x {
+                    Ok((k, _)) => k.starts_with(&prefix),
+                    Err(_) => true,
+                }),
+        ))
+    }
+}
+
+impl MetaBackend for RedbBackend {
+    fn get(&self, keyspace: Keyspace, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.read_table(keyspace)?
+            .get(key)
+            .map(|v| v.map(|v| v.value().to_vec()))
+            .map_err(map_err)
+    }
+
+    fn iter(&self, keyspace: Keyspace) -> BackendIter {
+        self.scan_prefix(keyspace, &[])
+    }
+
+    fn scan_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> BackendIter {
+        match self.range_prefix(keyspace, prefix) {
+            Ok(iter) => iter,
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn apply_batch(&self, batch: Batch) -> Result<()> {
+        let Db::Writable(db) = &self.db else {
+            return Err(ForcepError::ReadOnly);
+        };
+        let txn = db.begin_write().map_err(map_err)?;
+        {
+            let mut tables = Keyspace::ALL
+                .iter()
+                .map(|ks| txn.open_table(table(*ks)))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(map_err)?;
+            for op in batch.ops() {
+                match op {
+                    BatchOp::Insert {
+                        keyspace,
+                        key,
+                        value,
+                    } => {
+                        tables[keyspace.index()]
+                            .insert(key.as_slice(), value.as_slice())
+                            .map_err(map_err)?;
+                    }
+                    BatchOp::Remove { keyspace, key } => {
+                        tables[keyspace.index()]
+                            .remove(key.as_slice())
+                            .map_err(map_err)?;
+                    }
+                }
+            }
+        }
+        // dropping the transaction without committing aborts it
+        txn.commit().map_err(map_err)
+    }
+
+    fn is_empty(&self, keyspace: Keyspace) -> Result<bool> {
+        self.read_table(keyspace)?.is_empty().map_err(map_err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redb_backend() {
+        const PATH: &str = "./cache/test-index.redb";
+        std::fs::create_dir_all("./cache").unwrap();
+        let _ = std::fs::remove_file(PATH);
+        let backend = RedbBackend::open(PATH).unwrap();
+        crate::backends::test::exercise(&backend);
+
+        // read-only handles can't be opened next to a writable one
+        let e = RedbBackend::open_read_only(PATH).unwrap_err();
+        assert!(RedbBackend::is_already_open(&e));
+        drop(backend);
+        let backend = RedbBackend::open_read_only(PATH).unwrap();
+        let other = RedbBackend::open_read_only(PATH).unwrap();
+        assert_eq!(
+            backend.iter(Keyspace::Tags).count(),
+            other.iter(Keyspace::Tags).count()
+        );
+        assert!(matches!(
+            backend.insert(Keyspace::Tags, b"a", b"1"),
+            Err(ForcepError::ReadOnly)
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+use super::{BackendIter, Batch, BatchOp, Keyspace, MetaBackend};
+use crate::{ForcepError, Result};
+use std::path;
+
+/// A [`MetaBackend`] that stores metadata in a [`sled`] database. This is the default backend.
+///
+/// Every [`Keyspace`] is stored in its own tree, with [`Keyspace::Entries`] using the default
+/// tree of the database.
+///
+/// # Examples
+///
+/// ```rust
+/// use forceps::backends::{MetaBackend, Keyspace, SledBackend};
+///
+/// let backend = SledBackend::temporary().unwrap();
+/// backend.insert(Keyspace::Info, b"KEY", b"VALUE").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SledBackend {
+    /// The tree of every keyspace, indexed by [`Keyspace::index`]
+    trees: Vec<sled::Tree>,
+}
+
+impl SledBackend {
+    /// Opens (or creates) the sled database in the directory `path`.
+    pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Self> {
+        sled::open(path)
+            .map_err(ForcepError::MetaDb)
+            .and_then(Self::from_db)
+    }
+
+    /// Creates a sled database that is removed when the backend is dropped.
+    pub fn temporary() -> Result<Self> {
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .map_err(ForcepError::MetaDb)
+            .and_then(Self::from_db)
+    }
+
+    /// Creates the backend from an already opened sled database.
+    pub fn from_db(db: sled::Db) -> Result<Self> {
+        let trees = Keyspace::ALL
+            .iter()
+            .map(|ks| match ks {
+                // entries were stored in the default tree before keyspaces existed
+                Keyspace::Entries => Ok(sled::Tree::clone(&db)),
+                ks => db.open_tree(ks.name()),
+            })
+            .collect::<sled::Result<Vec<_>>>()
+            .map_err(ForcepError::MetaDb)?;
+        Ok(Self { trees })
+    }
+
+    #[inline]
+    fn tree(&self, keyspace: Keyspace) -> &sled::Tree {
+        &self.trees[keyspace.index()]
+    }
+}
+
+/// Converts a sled iterator into a [`BackendIter`]
+fn iter_from(iter: sled::Iter) -> BackendIter {
+    Box::new(iter.map(|x| match x {
+        Ok((k, v)) => Ok((k.to_vec(), v.to_vec())),
+        Err(e) => Err(ForcepError::MetaDb(e)),
+    }))
+}
+
+impl MetaBackend for SledBackend {
+    fn get(&self, keyspace: Keyspace, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tree(keyspace)
+            .get(key)
+            .map(|v| v.map(|v| v.to_vec()))
+            .map_err(ForcepError::MetaDb)
+    }
+
+    fn iter(&self, keyspace: Keyspace) -> BackendIter {
+        iter_from(self.tree(keyspace).iter())
+    }
+
+    fn scan_prefix(&self, keyspace: Keyspace, prefix: &[u8]) -> BackendIter {
+        iter_from(self.tree(keyspace).scan_prefix(prefix))
+    }
+
+    fn apply_batch(&self, batch: Batch) -> Result<()> {
+        use sled::{
+            Transactional,
+            transaction::{ConflictableTransactionResult, TransactionError},
+        };
+        use std::convert::Infallible;
+
+        self.trees
+            .as_slice()
+            .transaction(|trees| -> ConflictableTransactionResult<(), Infallible> {
+                for op in batch.ops() {
+                    match op {
+                        BatchOp::Insert {
+                            keyspace,
+                            key,
+                            value,
+                        } => {
+                            trees[keyspace.index()].insert(key.as_slice(), value.as_slice())?;
+                        }
+                        BatchOp::Remove { keyspace, key } => {
+                            trees[keyspace.index()].remove(key.as_slice())?;
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Abort(never) => match never {},
+                TransactionError::Storage(e) => ForcepError::MetaDb(e),
+            })
+    }
+
+    fn flush(&self) -> Result<()> {
+        // every tree shares the same log, so flushing one flushes the whole database
+        self.tree(Keyspace::Entries)
+            .flush()
+            .map(|_| ())
+            .map_err(ForcepError::MetaDb)
+    }
+
+    fn is_empty(&self, keyspace: Keyspace) -> Result<bool> {
+        Ok(self.tree(keyspace).is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sled_backend() {
+        crate::backends::test::exercise(&SledBackend::temporary().unwrap());
+    }
+}
@@ -1,11 +1,44 @@
+mod access_buffer;
 mod builder;
+mod check;
+mod durability;
+mod journal;
+mod key_lock;
+mod lock;
+mod quarantine;
+mod query;
+mod rebuild;
+mod scrub;
+mod snapshot;
+mod startup;
+mod transaction;
+mod write_options;
 pub use builder::CacheBuilder;
+pub use check::{CheckOptions, CheckReport};
+pub use durability::Durability;
+pub use key_lock::KeyGuard;
+pub use lock::LockMode;
+pub use quarantine::QuarantinedEntry;
+pub use query::{Query, QuerySort};
+pub use scrub::{ScrubAction, ScrubOptions, ScrubStatus};
+pub use startup::StartupReport;
+pub use transaction::Transaction;
+pub use write_options::WriteOptions;
 
-use crate::{ForcepError, MetaDb, Metadata, Result, mem_cache::MemCache};
+use crate::{
+    Attributes, ForcepError, HashAlgorithm, Intent, MetaDb, Metadata, Result, TimeIndex,
+    backends::MetaBackendKind, evictors::DynEvictor, mem_cache::MemCache,
+};
+use access_buffer::AccessBuffer;
 use bytes::Bytes;
+use key_lock::KeyLocks;
+use lock::DirLock;
+use parking_lot::Mutex;
 use std::io;
 use std::path;
 use std::result;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs as afs;
 
 /// Creates a writeable and persistent temporary file in the path provided, returning the path and
@@ -27,6 +60,22 @@ struct Options {
     path: path::PathBuf,
     dir_depth: u8,
     track_access: bool,
+    // interval to flush buffered access tracking at, `None` to track accesses immediately
+    access_flush_interval: Option<Duration>,
+    // interval to periodically flush the metadata database at
+    flush_interval: Option<Duration>,
+    durability: Durability,
+    lock_mode: LockMode,
+    read_only: bool,
+    // minimum age of orphaned temporary files to remove on startup, `None` to keep them
+    temp_sweep_age: Option<Duration>,
+    rebuild_index_on_failure: bool,
+    // background integrity scrubbing, `None` to disable it
+    scrub: Option<ScrubOptions>,
+    // evictor to run when a write fails because the disk is full
+    emergency_evictor: Option<Arc<dyn DynEvictor>>,
+    hash_alg: HashAlgorithm,
+    meta_backend: MetaBackendKind,
 
     // maximum size of the in-memory lru in bytes
     lru_size: usize,
@@ -36,6 +85,27 @@ struct Options {
     wbuff_sz: usize,
 }
 
+impl Options {
+    /// Creates a PathBuf based on the key provided
+    fn path_from_key(&self, key: &[u8]) -> path::PathBuf {
+        let hex = hex::encode(key);
+        let mut buf = self.path.clone();
+
+        // push segments of key as paths to the PathBuf. If the hex isn't long enough, then push
+        // "__" instead.
+        for n in (0..self.dir_depth).map(|x| x as usize * 2) {
+            let n_end = n + 2;
+            buf.push(if n_end >= hex.len() {
+                "__"
+            } else {
+                &hex[n..n_end]
+            })
+        }
+        buf.push(&hex);
+        buf
+    }
+}
+
 /// The main component of `forceps`, and  acts as the API for interacting with the on-disk cache.
 ///
 /// This structure includes the async `read`, `write`, and `remove` operations which are the basic
@@ -72,9 +142,22 @@ struct Options {
 /// [`CacheBuilder`]: crate::CacheBuilder
 #[derive(Debug)]
 pub struct Cache {
-    meta: MetaDb,
-    mem: MemCache,
+    meta: Arc<MetaDb>,
+    mem: Arc<MemCache>,
+    /// Locks serializing the writes and removals of each entry, which also keep reads from
+    /// seeing part of a transaction
+    locks: Arc<KeyLocks>,
+    /// Buffered access tracking updates, if buffering is enabled
+    access: Option<Arc<AccessBuffer>>,
+    /// Status of the background integrity scrubber, if it is enabled
+    scrub: Option<Arc<Mutex<ScrubStatus>>>,
+    startup: StartupReport,
     opts: Options,
+    /// The copy of the metadata database opened by read-only caches
+    _snapshot: Option<snapshot::Snapshot>,
+    /// The lock on the cache directory (unless read-only), declared last so it's released after
+    /// everything else
+    _lock: Option<DirLock>,
 }
 
 impl Cache {
@@ -101,49 +184,201 @@ impl Cache {
     }
 
     /// Creates a new Cache instance based on the CacheBuilder
-    async fn create(opts: Options) -> Result<Self> {
-        // create the base directory for the cache
-        afs::create_dir_all(&opts.path)
-            .await
-            .map_err(ForcepError::Io)?;
+    async fn create(mut opts: Options) -> Result<Self> {
+        let mut startup = StartupReport::default();
+        let (meta, snapshot, lock) = if opts.read_only {
+            // reads of a read-only cache must not write any metadata
+            opts.track_access = false;
+            let (meta, snapshot) = snapshot::open(&opts)?;
+            (meta, snapshot, None)
+        } else {
+            // create the base directory for the cache
+            afs::create_dir_all(&opts.path)
+                .await
+                .map_err(ForcepError::Io)?;
+            // nothing else may touch the directory before it's locked
+            let lock = DirLock::acquire(&opts.path, opts.lock_mode).await?;
+
+            let meta = match open_meta(&opts) {
+                Ok(meta) => meta,
+                Err(e) if opts.rebuild_index_on_failure && rebuild::is_corruption(&e) => {
+                    // backends that don't live in the cache directory can't be replaced
+                    let Some(storage) = opts.meta_backend.storage_path(&opts.path) else {
+                        return Err(e);
+                    };
+                    startup.corrupt_index = Some(rebuild::move_aside(&storage).await?);
+                    open_meta(&opts)?
+                }
+                Err(e) => return Err(e),
+            };
+            startup.intents_replayed = journal::replay(&meta, &opts).await?;
+            // interrupted operations may still need their temporary files until they're replayed
+            if let Some(min_age) = opts.temp_sweep_age {
+                startup::sweep_temp_files(&opts.path, min_age, &mut startup).await?;
+            }
+            (meta, None, Some(lock))
+        };
+        let meta = Arc::new(meta);
+
+        let access = match opts.access_flush_interval {
+            Some(interval) if opts.track_access => {
+                let buffer = Arc::new(AccessBuffer::default());
+                buffer.spawn_flusher(&meta, interval);
+                Some(buffer)
+            }
+            _ => None,
+        };
+        if let Some(interval) = opts.flush_interval {
+            spawn_flusher(&meta, access.as_ref(), interval);
+        }
 
-        let mut meta_path = opts.path.clone();
-        meta_path.push("index");
-        Ok(Self {
-            meta: MetaDb::new(&meta_path)?,
-            mem: MemCache::new(opts.lru_size),
+        let mut cache = Self {
+            meta,
+            mem: Arc::new(MemCache::new(opts.lru_size)),
+            locks: Arc::default(),
+            access,
+            scrub: None,
+            startup,
             opts,
-        })
+            _snapshot: snapshot,
+            _lock: lock,
+        };
+        if cache.startup.corrupt_index.is_some() {
+            cache.startup.entries_restored = cache.rebuild_index().await?;
+        }
+        // only start scrubbing once the index is complete again
+        if let Some(scrub) = cache.opts.scrub.clone() {
+            cache.scrub = Some(scrub::spawn(
+                &cache.meta,
+                &cache.mem,
+                &cache.locks,
+                cache.access.as_ref(),
+                cache.opts.clone(),
+                scrub,
+            ));
+        }
+        Ok(cache)
+    }
+
+    /// Retrieves the [`StartupReport`] with information about the work done while building this
+    /// cache, such as the number of orphaned temporary files that were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let report = cache.startup_report();
+    /// println!("reclaimed {} bytes", report.temp_bytes_reclaimed);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn startup_report(&self) -> &StartupReport {
+        &self.startup
     }
 
     /// Creates a PathBuf based on the key provided
+    #[inline]
     fn path_from_key(&self, key: &[u8]) -> path::PathBuf {
-        let hex = hex::encode(key);
-        let mut buf = self.opts.path.clone();
+        self.opts.path_from_key(key)
+    }
 
-        // push segments of key as paths to the PathBuf. If the hex isn't long enough, then push
-        // "__" instead.
-        for n in (0..self.opts.dir_depth).map(|x| x as usize * 2) {
-            let n_end = n + 2;
-            buf.push(if n_end >= hex.len() {
-                "__"
-            } else {
-                &hex[n..n_end]
-            })
+    /// Fails with [`ForcepError::ReadOnly`] if the cache was opened in read-only mode
+    #[inline]
+    fn ensure_writable(&self) -> Result<()> {
+        if self.opts.read_only {
+            return Err(ForcepError::ReadOnly);
         }
-        buf.push(&hex);
-        buf
+        Ok(())
     }
 
     /// Tracks the access for a cache entry if the option is enabled
     #[inline]
-    fn track_access_for(&self, k: &[u8], meta: Metadata) -> Result<()> {
-        if self.opts.track_access {
-            self.meta.track_access_for(k, Some(meta))?;
+    fn track_access_for(&self, k: &[u8]) -> Result<()> {
+        if let Some(access) = &self.access {
+            access.record(k);
+        } else if self.opts.track_access {
+            self.meta.track_access_for(k)?;
         }
         Ok(())
     }
 
+    /// Retrieves the progress and results of the background integrity scrubber, or `None` if it
+    /// isn't enabled. See [`CacheBuilder::scrub`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, ScrubOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .scrub(ScrubOptions::new())
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let status = cache.scrub_status().unwrap();
+    /// println!("{} corrupted entries", status.corrupted.len());
+    /// # }
+    /// ```
+    pub fn scrub_status(&self) -> Option<ScrubStatus> {
+        self.scrub.as_ref().map(|status| status.lock().clone())
+    }
+
+    /// Persists all metadata to disk, blocking until it is done.
+    ///
+    /// The metadata database may buffer writes and persist them on its own schedule, so the
+    /// metadata of recently written entries can be lost if the process crashes before then.
+    /// Any buffered access tracking updates are written before flushing.
+    ///
+    /// The cache is also flushed when it is dropped, and can be flushed periodically with
+    /// [`CacheBuilder::flush_interval`].
+    ///
+    /// # Non-Async
+    ///
+    /// This function blocks until the flush has completed. See
+    /// [`flush_async`](Self::flush_async) for a version that doesn't block the async runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// cache.write(b"MY_KEY", b"Hello World").await.unwrap();
+    /// cache.flush().unwrap();
+    /// # }
+    /// ```
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
+        flush_all(&self.meta, self.access.as_deref())
+    }
+
+    /// Persists all metadata to disk like [`flush`](Self::flush), but runs the blocking flush on
+    /// a separate thread.
+    pub async fn flush_async(&self) -> Result<()> {
+        let meta = Arc::clone(&self.meta);
+        let access = self.access.clone();
+        tokio::task::spawn_blocking(move || flush_all(&meta, access.as_deref()))
+            .await
+            .map_err(|e| ForcepError::Io(io::Error::other(e)))?
+    }
+
     /// Reads an entry from the database, returning a vector of bytes that represent the entry.
     ///
     /// # Not Found
@@ -177,6 +412,8 @@ impl Cache {
     pub async fn read<K: AsRef<[u8]>>(&self, key: K) -> Result<Bytes> {
         use tokio::io::AsyncReadExt;
         let k = key.as_ref();
+        // the metadata and the file must belong to the same side of a transaction
+        let _published = self.locks.read(k).await;
 
         // read the metadata to reduce miss cost, since the metadata DB should generally fit in
         // memory (and also removes the need to read file metadata for a hit.)
@@ -184,7 +421,7 @@ impl Cache {
 
         // look in the memory cache to see if it's there and return if it is
         if let Some(val) = self.mem.get(k) {
-            return self.track_access_for(k, meta).map(|_| val);
+            return self.track_access_for(k).map(|_| val);
         }
 
         let file = {
@@ -208,7 +445,7 @@ impl Cache {
             .await
             .map_err(ForcepError::Io)?;
 
-        self.track_access_for(k, meta)?;
+        self.track_access_for(k)?;
         let bytes = Bytes::from(buf);
         self.mem.put(k, Bytes::clone(&bytes));
         Ok(bytes)
@@ -232,41 +469,182 @@ impl Cache {
     /// cache.write(b"MY_KEY", b"Hello World").await.unwrap();
     /// # }
     /// ```
+    #[inline]
     pub async fn write<K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
         key: K,
         value: V,
     ) -> Result<Metadata> {
-        use tokio::io::AsyncWriteExt;
+        self.write_with(key, value, WriteOptions::default()).await
+    }
+
+    /// Writes an entry with the specified key to the cache database using the provided
+    /// [`WriteOptions`]. This behaves exactly like [`write`](Self::write), but also stores the
+    /// extra information from the options (such as user attributes) in the entry's metadata.
+    ///
+    /// If [`WriteOptions::if_version`] is set and the entry has a different version, this fails
+    /// with [`ForcepError::VersionMismatch`] without changing the entry. If the disk is full, this
+    /// fails with [`ForcepError::StorageFull`], see [`CacheBuilder::emergency_evictor`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, WriteOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let opts = WriteOptions::new().attribute("content-type", "text/plain");
+    /// cache.write_with(b"MY_KEY", b"Hello World", opts).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn write_with<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+        opts: WriteOptions,
+    ) -> Result<Metadata> {
+        self.ensure_writable()?;
         let key = key.as_ref();
         let value = value.as_ref();
 
-        let (tmp, tmp_path) = tempfile(&self.opts.path).await?;
-        // write all data to a temporary file to allow for atomic replacement and simultaneous reads.
-        {
-            let mut writer = tokio::io::BufWriter::with_capacity(self.opts.wbuff_sz, tmp);
-            writer.write_all(value).await.map_err(ForcepError::Io)?;
-            writer.flush().await.map_err(ForcepError::Io)?;
-        }
+        // the data is staged before locking the entry, so an emergency eviction doesn't wait for
+        // the lock held by this write
+        let tmp_path = self.stage_or_evict(value).await?;
+        let _guard = self.locks.lock(key).await;
+        self.finish_write(key, value, tmp_path, opts).await
+    }
+
+    /// Writes an entry like [`write_with`](Self::write_with), while the lock of `key` is already
+    /// held by the caller.
+    async fn write_locked(&self, key: &[u8], value: &[u8], opts: WriteOptions) -> Result<Metadata> {
+        self.ensure_writable()?;
+        let tmp_path = self.stage_or_evict(value).await?;
+        self.finish_write(key, value, tmp_path, opts).await
+    }
 
+    /// Replaces the entry `key` with the data `value` staged at `tmp_path`.
+    async fn finish_write(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        tmp_path: path::PathBuf,
+        opts: WriteOptions,
+    ) -> Result<Metadata> {
         // move the temporary file to the final destination
         let final_path = self.path_from_key(key);
-        if let Some(parent) = final_path.parent() {
-            afs::create_dir_all(parent).await.map_err(ForcepError::Io)?;
+        let tags = opts.tags.into_iter().collect();
+        let meta = self.meta.new_metadata(value, opts.attributes, tags);
+        let res = self
+            .publish_write(key, &tmp_path, &final_path, meta, opts.if_version)
+            .await
+            .map_err(storage_full);
+        if res.is_err() {
+            let _ = afs::remove_file(&tmp_path).await;
+            // the file may have been replaced without its metadata
+            self.mem.remove(key);
         }
-        afs::rename(&tmp_path, &final_path)
+        let meta = res?;
+        self.opts
+            .durability
+            .sync_parent(&final_path)
             .await
-            .map_err(ForcepError::Io)?;
+            .map_err(storage_full)?;
 
         if !self.mem.is_nil() {
             self.mem.put(key, Bytes::from(Vec::from(value)));
         }
-        self.meta.insert_metadata_for(key, value)
+        // accesses of the previous value shouldn't count towards the new one
+        if let Some(access) = &self.access {
+            access.discard(key);
+        }
+        Ok(meta)
+    }
+
+    /// Moves the staged file at `tmp_path` to `final_path` and inserts `meta` for the entry `key`,
+    /// keeping the write in the journal until both are done.
+    async fn publish_write(
+        &self,
+        key: &[u8],
+        tmp_path: &path::Path,
+        final_path: &path::Path,
+        meta: Metadata,
+        if_version: Option<u64>,
+    ) -> Result<Metadata> {
+        // the caller holds the lock of the entry, so its version can't change until it's replaced
+        if let Some(expected) = if_version {
+            let actual = self.meta.get_metadata_opt(key)?.map(|m| m.get_version());
+            if actual.unwrap_or(0) != expected {
+                return Err(ForcepError::VersionMismatch { expected, actual });
+            }
+        }
+        if let Some(parent) = final_path.parent() {
+            afs::create_dir_all(parent).await.map_err(ForcepError::Io)?;
+        }
+        let intent = self.begin_intent(&Intent::Write {
+            key: key.to_vec(),
+            tmp: journal::tmp_name(tmp_path)?,
+            meta: meta.clone(),
+        })?;
+        let res = match afs::rename(tmp_path, final_path).await {
+            Ok(()) => self.meta.insert_metadata_with(key, meta, if_version),
+            Err(e) => Err(ForcepError::Io(e)),
+        };
+        // if the file was moved but the metadata couldn't be written, the intent is kept so the
+        // write is completed the next time the cache is built
+        if res.is_ok() || afs::try_exists(tmp_path).await.unwrap_or(false) {
+            self.meta.end_intent(intent)?;
+        }
+        res
+    }
+
+    /// Records `intent` in the journal, returning its id, see [`journal::begin`].
+    #[inline]
+    fn begin_intent(&self, intent: &Intent) -> Result<u64> {
+        journal::begin(&self.meta, &self.opts, intent)
+    }
+
+    /// Stages `value` like [`stage`](Self::stage), but if the disk is full, the emergency evictor
+    /// (if any) is run and staging is retried once.
+    async fn stage_or_evict(&self, value: &[u8]) -> Result<path::PathBuf> {
+        match (self.stage(value).await, &self.opts.emergency_evictor) {
+            (Err(ForcepError::StorageFull(_)), Some(evictor)) => {
+                evictor.evict_boxed(self).await?;
+                self.stage(value).await
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Writes `value` to a new temporary file in the cache directory, returning its path.
+    ///
+    /// The file is removed again if writing fails, which fails with [`ForcepError::StorageFull`]
+    /// if the disk is full.
+    async fn stage(&self, value: &[u8]) -> Result<path::PathBuf> {
+        use tokio::io::AsyncWriteExt;
+        let (tmp, tmp_path) = tempfile(&self.opts.path).await.map_err(storage_full)?;
+        let res = async {
+            let mut writer = tokio::io::BufWriter::with_capacity(self.opts.wbuff_sz, tmp);
+            writer.write_all(value).await.map_err(ForcepError::Io)?;
+            writer.flush().await.map_err(ForcepError::Io)?;
+            self.opts.durability.sync_file(writer.get_ref()).await
+        }
+        .await;
+        if let Err(e) = res {
+            let _ = afs::remove_file(&tmp_path).await;
+            return Err(storage_full(e));
+        }
+        Ok(tmp_path)
     }
 
     /// Removes an entry from the cache, returning its [`Metadata`].
     ///
-    /// This will remove the entry from both the main cache database and the metadata database.
+    /// This will remove the entry from the main cache database, the metadata database, and the
+    /// in-memory cache (if enabled).
     /// Please note that this will return `Error::NotFound` if either the main database *or* the
     /// meta database didn't find the entry.
     ///
@@ -289,25 +667,22 @@ impl Cache {
     /// ```
     pub async fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Metadata> {
         let key = key.as_ref();
+        let _guard = self.locks.lock(key).await;
+        self.remove_locked(key).await
+    }
 
-        let cur_path = self.path_from_key(key);
-        let tmp_path = crate::tmp::tmppath_in(&self.opts.path);
-
-        // move then delete the file
-        //
-        // the purpose of moving then deleting is that file moves are much faster than file
-        // deletes. if we were to delete in place, and another thread starts reading, it could
-        // spell bad news.
-        afs::rename(&cur_path, &tmp_path)
-            .await
-            .map_err(|e| match e.kind() {
-                io::ErrorKind::NotFound => ForcepError::NotFound,
-                _ => ForcepError::Io(e),
-            })?;
-        afs::remove_file(&tmp_path).await.map_err(ForcepError::Io)?;
-
-        // remove the metadata for the entry
-        self.meta.remove_metadata_for(key)
+    /// Removes an entry like [`remove`](Self::remove), while the lock of `key` is already held by
+    /// the caller.
+    async fn remove_locked(&self, key: &[u8]) -> Result<Metadata> {
+        self.ensure_writable()?;
+        remove_entry(
+            &self.meta,
+            &self.mem,
+            self.access.as_deref(),
+            &self.opts,
+            key,
+        )
+        .await
     }
 
     /// Queries the index database for metadata on the entry with the corresponding key.
@@ -325,6 +700,12 @@ impl Cache {
     /// If the entry is not found, then it will return
     /// `Err(`[`Error::NotFound`](ForcepError::NotFound)`)`.
     ///
+    /// # Buffered Access Tracking
+    ///
+    /// When [`CacheBuilder::buffer_access_tracking`] is used, accesses that haven't been flushed
+    /// yet are included in the returned metadata. Other ways of reading metadata (like
+    /// [`metadata_iter`](Self::metadata_iter)) only see flushed accesses.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -344,7 +725,64 @@ impl Cache {
     /// ```
     #[inline]
     pub fn read_metadata<K: AsRef<[u8]>>(&self, key: K) -> Result<Metadata> {
-        self.meta.get_metadata(key.as_ref())
+        let key = key.as_ref();
+        let mut meta = self.meta.get_metadata(key)?;
+        self.merge_pending_access(key, &mut meta);
+        Ok(meta)
+    }
+
+    /// Applies the buffered accesses of `key` that haven't been flushed yet to `meta`
+    #[inline]
+    fn merge_pending_access(&self, key: &[u8], meta: &mut Metadata) {
+        if let Some(access) = &self.access {
+            access.merge_into(key, meta);
+        }
+    }
+
+    /// Updates the user-defined attributes of an entry without rewriting its value.
+    ///
+    /// The closure `f` is given mutable access to the entry's current [`Attributes`], allowing
+    /// attributes to be inserted, changed, or removed. The updated [`Metadata`] is returned.
+    ///
+    /// # Non-Async
+    ///
+    /// Note that this function is not an async call. This is because the backend database used,
+    /// `sled`, is not async-compatible. However, these calls are instead very fast.
+    ///
+    /// # Not Found
+    ///
+    /// If the entry is not found, then it will return
+    /// `Err(`[`Error::MetaNotFound`](ForcepError::MetaNotFound)`)`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// # cache.write(b"MY_KEY", b"Hello World").await.unwrap();
+    /// let meta = cache
+    ///     .update_attributes(b"MY_KEY", |attrs| {
+    ///         attrs.insert("origin".to_owned(), "https://example.com".into());
+    ///     })
+    ///     .unwrap();
+    /// assert!(meta.get_attribute("origin").is_some());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn update_attributes<K, F>(&self, key: K, f: F) -> Result<Metadata>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(&mut Attributes),
+    {
+        self.ensure_writable()?;
+        self.meta.update_attributes(key.as_ref(), f)
     }
 
     /// An iterator over the entire metadata database, which provides metadata for every entry.
@@ -381,52 +819,494 @@ impl Cache {
         self.meta.metadata_iter()
     }
 
-    /// Runs the specified eviction algorithm over this instance cache instance.
+    /// The total size (in bytes) of every entry in the cache.
     ///
-    /// Eviction algorithms will remove items out of the cache until certain a condition has been
-    /// met, usually a size requirement. See the [`evictors`] module for more information and
-    /// examples.
+    /// This is read from a counter that is kept up to date on every write and removal, so it
+    /// doesn't require iterating over the metadata.
     ///
-    /// [`evictors`]: crate::evictors
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// println!("cache is using {} bytes", cache.total_size().unwrap());
+    /// # }
+    /// ```
     #[inline]
-    pub async fn evict_with<E>(&self, evictor: E) -> result::Result<u64, E::Err>
-    where
-        E: crate::evictors::Evictor,
-    {
-        evictor.evict(self).await
+    pub fn total_size(&self) -> Result<u64> {
+        self.meta.total_size()
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::CacheBuilder;
 
-    async fn default_cache() -> Cache {
-        CacheBuilder::default().build().await.unwrap()
+    /// The number of entries in the cache.
+    ///
+    /// Like [`total_size`](Self::total_size), this is read from a counter and is not computed by
+    /// iterating over the metadata.
+    #[inline]
+    pub fn len(&self) -> Result<u64> {
+        self.meta.entry_count()
     }
 
-    #[tokio::test]
-    async fn short_path() {
-        let cache = default_cache().await;
-        cache.path_from_key(&[0xAA]);
-        cache.path_from_key(&[0xAA, 0xBB]);
-        cache.path_from_key(&[0xAA, 0xBB, 0xCC]);
+    /// Whether the cache has no entries.
+    #[inline]
+    pub fn is_empty(&self) -> Result<bool> {
+        self.len().map(|len| len == 0)
     }
 
-    #[tokio::test]
-    async fn write_read_remove() {
-        let cache = default_cache().await;
-
-        cache.write(&b"CACHE_KEY", &b"Hello World").await.unwrap();
-        let data = cache.read(&b"CACHE_KEY").await.unwrap();
+    /// An iterator over the keys of every entry that was written with the tag `tag`.
+    ///
+    /// Tags are set when writing an entry using [`WriteOptions::tag`].
+    ///
+    /// # Non-Async
+    ///
+    /// Note that this function is not an async call. This is because the backend database used,
+    /// `sled`, is not async-compatible. However, these calls are instead very fast.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, WriteOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let opts = WriteOptions::new().tag("rev-1");
+    /// cache.write_with(b"MY_KEY", b"Hello World", opts).await.unwrap();
+    /// for result in cache.keys_with_tag("rev-1") {
+    ///     let key = result.unwrap();
+    ///     println!("{}", String::from_utf8_lossy(&key))
+    /// }
+    /// # }
+    /// ```
+    #[inline]
+    pub fn keys_with_tag(&self, tag: &str) -> impl Iterator<Item = Result<Vec<u8>>> + use<> {
+        self.meta.keys_with_tag(tag)
+    }
+
+    /// Removes every entry that was written with the tag `tag`, returning the number of entries
+    /// removed.
+    ///
+    /// Entries are removed exactly as if [`remove`](Self::remove) was called for each of them, so
+    /// the tag index is kept consistent.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, WriteOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let opts = WriteOptions::new().tag("rev-1");
+    /// cache.write_with(b"MY_KEY", b"Hello World", opts).await.unwrap();
+    /// cache.invalidate_tag("rev-1").await.unwrap();
+    /// assert!(cache.read(b"MY_KEY").await.is_err());
+    /// # }
+    /// ```
+    pub async fn invalidate_tag(&self, tag: &str) -> Result<usize> {
+        self.ensure_writable()?;
+        // collect the keys first so the tag index isn't being modified while iterating it
+        let keys = self.keys_with_tag(tag).collect::<Result<Vec<_>>>()?;
+
+        let mut removed = 0;
+        for key in keys {
+            // the lock is held through the cleanup below, so a concurrent write of the entry
+            // can't be published in between and lose its metadata
+            let _guard = self.locks.lock(&key).await;
+            match self.remove_locked(&key).await {
+                Ok(_) => removed += 1,
+                // someone else removed the entry in the meantime
+                Err(ForcepError::MetaNotFound) => {}
+                // the file is already gone, but the metadata (and tag index) still need cleaning
+                Err(ForcepError::NotFound) => {
+                    let path = self.path_from_key(&key);
+                    if afs::try_exists(&path).await.map_err(ForcepError::Io)? {
+                        continue;
+                    }
+                    match self.meta.remove_metadata_for(&key) {
+                        Ok(_) | Err(ForcepError::MetaNotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                    if let Some(access) = &self.access {
+                        access.discard(&key);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Retrieves the keys of (at most) the `n` oldest entries according to `index`, oldest first.
+    ///
+    /// Used by the evictors to find candidates without scanning the whole metadata database.
+    #[inline]
+    pub(crate) fn oldest_keys(&self, index: TimeIndex, n: usize) -> Result<Vec<Vec<u8>>> {
+        self.meta.oldest_keys(index, n)
+    }
+
+    /// Removes the entry `key` for an evictor like [`remove`](Self::remove), unless it's locked
+    /// by a [`KeyGuard`], in which case `None` is returned right away.
+    pub(crate) async fn evict_entry(&self, key: &[u8]) -> Result<Option<Metadata>> {
+        let Some(_guard) = self.locks.lock_unguarded(key).await else {
+            return Ok(None);
+        };
+        self.remove_locked(key).await.map(Some)
+    }
+
+    /// Locks the entry `key` until the returned [`KeyGuard`] is dropped, waiting for any other
+    /// write or removal of the entry to finish first.
+    ///
+    /// Writes, removals and transactions of the entry wait for the lock (and evictions skip it),
+    /// which makes read-modify-write sequences safe. The entry has to be changed through the guard
+    /// while it's held, see [`KeyGuard`] for more information and examples.
+    pub async fn lock_key<K: AsRef<[u8]>>(&self, key: K) -> KeyGuard<'_> {
+        KeyGuard::lock(self, key.as_ref().to_vec()).await
+    }
+
+    /// Creates a new [`Transaction`], which applies several writes and removals atomically once
+    /// it is committed.
+    ///
+    /// See [`Transaction`] for more information and examples.
+    #[inline]
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Creates a new [`Query`] over the entries of this cache.
+    ///
+    /// See [`Query`] for the available conditions and examples.
+    #[inline]
+    pub fn query(&self) -> Query<'_> {
+        Query::new(self)
+    }
+
+    /// Rebuilds the metadata of every entry file on disk that has no metadata, returning the
+    /// number of entries that were restored.
+    ///
+    /// The keys of the entries are decoded from their file names, and the size and integrity
+    /// are computed from their data. Everything else that can't be recovered from the file
+    /// (attributes, tags, and access statistics) is reset, and every timestamp is set to the
+    /// modification time of the file. Entries that still have metadata are left untouched.
+    ///
+    /// This is useful when the metadata database was lost, see also
+    /// [`CacheBuilder::rebuild_index_on_failure`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let restored = cache.rebuild_index().await.unwrap();
+    /// println!("restored {restored} entries");
+    /// # }
+    /// ```
+    #[inline]
+    pub async fn rebuild_index(&self) -> Result<u64> {
+        self.ensure_writable()?;
+        rebuild::run(self).await
+    }
+
+    /// Checks the consistency between the entry files on disk and the metadata database, and
+    /// optionally repairs any problems that are found.
+    ///
+    /// The check walks both the directory tree of the cache and the metadata database, and
+    /// reports:
+    ///
+    /// * Orphan files, which exist on disk but have no metadata
+    /// * Dangling metadata, whose entry file doesn't exist
+    /// * Entries whose file size doesn't match their metadata
+    /// * Entries whose data doesn't match their integrity hash (if enabled in the options)
+    ///
+    /// See [`CheckOptions`] for what is done to repair each problem. Checking a cache that is
+    /// being written to at the same time may report entries that are in the middle of being
+    /// written. Repairs are safe regardless, since every entry is locked and checked again before
+    /// it's repaired, and entries that changed in the meantime are left alone.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, CheckOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let report = cache.check(CheckOptions::new()).await.unwrap();
+    /// if !report.is_clean() {
+    ///     println!("found problems: {report:?}");
+    /// }
+    /// # }
+    /// ```
+    #[inline]
+    pub async fn check(&self, opts: CheckOptions) -> Result<CheckReport> {
+        check::run(self, opts).await
+    }
+
+    /// Lists every entry in the quarantine area of the cache, oldest first.
+    ///
+    /// Entries that fail verification are moved to the quarantine area (the `quarantine`
+    /// directory in the cache directory) instead of being deleted, if configured with
+    /// [`ScrubAction::Quarantine`] or [`CheckOptions::quarantine`]. Their data and metadata are
+    /// kept until they're restored or purged.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::Cache;
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// for entry in cache.quarantined().await.unwrap() {
+    ///     println!("{:?} was quarantined at {:?}", entry.key, entry.quarantined_at);
+    /// }
+    /// # }
+    /// ```
+    #[inline]
+    pub async fn quarantined(&self) -> Result<Vec<QuarantinedEntry>> {
+        quarantine::list(&self.opts).await
+    }
+
+    /// Writes the data of the quarantined entry `id` back to the cache, returning the new
+    /// [`Metadata`] of the entry.
+    ///
+    /// The data is accepted as it is, so the integrity of the restored entry is computed from it
+    /// again. The attributes and tags of the entry are kept, and any value written to the key
+    /// since it was quarantined is replaced. Fails with [`ForcepError::NotFound`] if there is no
+    /// quarantined entry `id`.
+    #[inline]
+    pub async fn restore_quarantined(&self, id: &str) -> Result<Metadata> {
+        quarantine::restore(self, id).await
+    }
+
+    /// Deletes the quarantined entry `id` for good.
+    ///
+    /// Fails with [`ForcepError::NotFound`] if there is no quarantined entry `id`.
+    #[inline]
+    pub async fn purge_quarantined(&self, id: &str) -> Result<()> {
+        self.ensure_writable()?;
+        quarantine::purge(&self.opts, id).await
+    }
+
+    /// Deletes every quarantined entry for good, returning how many there were.
+    #[inline]
+    pub async fn purge_quarantine(&self) -> Result<u64> {
+        self.ensure_writable()?;
+        quarantine::purge_all(&self.opts).await
+    }
+
+    /// Runs the specified eviction algorithm over this instance cache instance.
+    ///
+    /// Eviction algorithms will remove items out of the cache until certain a condition has been
+    /// met, usually a size requirement. See the [`evictors`] module for more information and
+    /// examples.
+    ///
+    /// [`evictors`]: crate::evictors
+    #[inline]
+    pub async fn evict_with<E>(&self, evictor: E) -> result::Result<u64, E::Err>
+    where
+        E: crate::evictors::Evictor,
+    {
+        evictor.evict(self).await
+    }
+}
+
+/// Removes the entry `key` from the cache described by `opts`, journaling the removal and
+/// invalidating its data in `mem` and its buffered accesses in `access`.
+///
+/// The caller must hold the lock of `key`. This is shared by [`Cache::remove`] and the background
+/// scrubber, which doesn't have a [`Cache`] to call it on.
+async fn remove_entry(
+    meta: &MetaDb,
+    mem: &MemCache,
+    access: Option<&AccessBuffer>,
+    opts: &Options,
+    key: &[u8],
+) -> Result<Metadata> {
+    let cur_path = opts.path_from_key(key);
+    let tmp_path = crate::tmp::tmppath_in(&opts.path);
+    let intent = journal::begin(
+        meta,
+        opts,
+        &Intent::Remove {
+            key: key.to_vec(),
+            tmp: journal::tmp_name(&tmp_path)?,
+        },
+    )?;
+
+    // move then delete the file
+    //
+    // the purpose of moving then deleting is that file moves are much faster than file
+    // deletes. if we were to delete in place, and another thread starts reading, it could
+    // spell bad news.
+    let renamed = afs::rename(&cur_path, &tmp_path).await;
+    // whether or not the file was there, its data must not be served from memory anymore
+    mem.remove(key);
+    if let Err(e) = renamed {
+        // nothing was changed
+        meta.end_intent(intent)?;
+        return Err(match e.kind() {
+            io::ErrorKind::NotFound => ForcepError::NotFound,
+            _ => ForcepError::Io(e),
+        });
+    }
+    afs::remove_file(&tmp_path).await.map_err(ForcepError::Io)?;
+    opts.durability.sync_parent(&cur_path).await?;
+
+    // remove the metadata for the entry
+    let res = meta.remove_metadata_for(key);
+    if let Ok(_) | Err(ForcepError::MetaNotFound) = res {
+        meta.end_intent(intent)?;
+    }
+    let mut meta = res?;
+    if let Some(access) = access {
+        access.merge_into(key, &mut meta);
+        access.discard(key);
+    }
+    Ok(meta)
+}
+
+/// Turns I/O errors caused by a full disk (or quota) into [`ForcepError::StorageFull`], including
+/// the ones reported by the metadata backends
+fn storage_full(e: ForcepError) -> ForcepError {
+    let is_full = |e: &io::Error| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+        )
+    };
+    match e {
+        ForcepError::Io(e) | ForcepError::MetaDb(sled::Error::Io(e)) if is_full(&e) => {
+            ForcepError::StorageFull(e)
+        }
+        #[cfg(feature = "redb")]
+        ForcepError::Backend(e) => match e.downcast::<redb::Error>() {
+            Ok(e) => match *e {
+                redb::Error::Io(e) if is_full(&e) => ForcepError::StorageFull(e),
+                e => ForcepError::Backend(Box::new(e)),
+            },
+            Err(e) => ForcepError::Backend(e),
+        },
+        e => e,
+    }
+}
+
+/// Opens the metadata database for the cache described by `opts`, upgrading it to the current
+/// format version
+fn open_meta(opts: &Options) -> Result<MetaDb> {
+    let backend = opts.meta_backend.open(&opts.path)?;
+    let meta = MetaDb::new(backend, opts.hash_alg)?;
+    // upgrade metadata written by older versions before anything else touches it
+    meta.migrate()?;
+    Ok(meta)
+}
+
+/// Writes the buffered access tracking updates in `access` (if any) and flushes `meta`
+fn flush_all(meta: &MetaDb, access: Option<&AccessBuffer>) -> Result<()> {
+    if let Some(access) = access {
+        access.flush(meta)?;
+    }
+    meta.flush()
+}
+
+/// Spawns a task that flushes `meta` (and `access`) every `interval`.
+///
+/// The task only holds weak references, so it stops by itself once the cache is dropped.
+fn spawn_flusher(meta: &Arc<MetaDb>, access: Option<&Arc<AccessBuffer>>, interval: Duration) {
+    let meta = Arc::downgrade(meta);
+    let access = access.map(Arc::downgrade);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // the first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(meta) = meta.upgrade() else {
+                break;
+            };
+            let access = access.as_ref().and_then(|a| a.upgrade());
+            // errors can't be reported from here, the next flush will simply try again
+            let _ = tokio::task::spawn_blocking(move || flush_all(&meta, access.as_deref())).await;
+        }
+    });
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        // errors can't be reported from here, so anything that wasn't flushed yet is left to the
+        // metadata database
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CacheBuilder;
+
+    /// Clears the directory of the test `name` and returns its path, so tests never share a
+    /// cache directory
+    fn test_dir(name: &str) -> path::PathBuf {
+        let path = path::PathBuf::from(format!("./cache/test-{name}"));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    /// Builds a cache with the default options in the fresh directory of the test `name`
+    async fn test_cache(name: &str) -> Cache {
+        CacheBuilder::new(test_dir(name)).build().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn short_path() {
+        let cache = test_cache("short-path").await;
+        cache.path_from_key(&[0xAA]);
+        cache.path_from_key(&[0xAA, 0xBB]);
+        cache.path_from_key(&[0xAA, 0xBB, 0xCC]);
+    }
+
+    #[tokio::test]
+    async fn write_read_remove() {
+        let cache = test_cache("write-read-remove").await;
+
+        cache.write(&b"CACHE_KEY", &b"Hello World").await.unwrap();
+        let data = cache.read(&b"CACHE_KEY").await.unwrap();
         assert_eq!(data.as_ref(), b"Hello World");
         cache.remove(&b"CACHE_KEY").await.unwrap();
     }
 
     #[tokio::test]
     async fn tracking_test() {
-        let cache = CacheBuilder::default()
+        let cache = CacheBuilder::new(test_dir("tracking"))
             .track_access(true)
             .build()
             .await
@@ -439,12 +1319,1184 @@ mod test {
         assert_eq!(cache.read_metadata(b"CACHE_KEY").unwrap().get_hits(), 100);
     }
 
+    #[tokio::test]
+    async fn buffered_tracking() {
+        use crate::backends::MemoryBackend;
+
+        let backend = MetaBackendKind::Custom(Arc::new(MemoryBackend::new()));
+        let path = test_dir("buffered-tracking");
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(backend.clone())
+            .track_access(true)
+            .buffer_access_tracking(Duration::from_secs(3600))
+            .build()
+            .await
+            .unwrap();
+
+        cache.write(b"CACHE_KEY", b"Hello World").await.unwrap();
+        for _ in 0..100 {
+            cache.read(b"CACHE_KEY").await.unwrap();
+        }
+        // unflushed accesses are merged into the metadata
+        assert_eq!(cache.read_metadata(b"CACHE_KEY").unwrap().get_hits(), 100);
+        assert_eq!(
+            cache.metadata_iter().next().unwrap().unwrap().1.get_hits(),
+            0
+        );
+
+        // dropping the cache flushes the buffer
+        drop(cache);
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(backend)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(cache.read_metadata(b"CACHE_KEY").unwrap().get_hits(), 100);
+        cache.remove(b"CACHE_KEY").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_versions() {
+        let cache = test_cache("write-versions").await;
+        let _ = cache.remove(b"VERSION_CACHE_KEY").await;
+
+        let opts = WriteOptions::new().if_version(0);
+        let first = cache
+            .write_with(b"VERSION_CACHE_KEY", b"Hello World", opts.clone())
+            .await
+            .unwrap();
+        assert_eq!(first.get_version(), 1);
+        // the entry exists now, so creating it again must fail
+        assert!(matches!(
+            cache.write_with(b"VERSION_CACHE_KEY", b"Hello", opts).await,
+            Err(ForcepError::VersionMismatch {
+                expected: 0,
+                actual: Some(1)
+            })
+        ));
+        let data = cache.read(b"VERSION_CACHE_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let second = cache
+            .write_with(
+                b"VERSION_CACHE_KEY",
+                b"Hello",
+                WriteOptions::new().if_version(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.get_version(), 2);
+        assert_eq!(second.get_created_at_raw(), first.get_created_at_raw());
+        assert!(second.get_last_modified_raw() > first.get_last_modified_raw());
+        let data = cache.read(b"VERSION_CACHE_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello");
+        cache.remove(b"VERSION_CACHE_KEY").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_buffered_access() {
+        let cache = CacheBuilder::new(test_dir("query-buffered-access"))
+            .meta_backend(MetaBackendKind::Memory)
+            .track_access(true)
+            .buffer_access_tracking(Duration::from_secs(3600))
+            .build()
+            .await
+            .unwrap();
+        for key in [b"QUERY_KEY1", b"QUERY_KEY2", b"QUERY_KEY3"] {
+            cache.write(key, b"Hello World").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        // the access is only buffered, so the index still has the entry as the oldest one
+        cache.read(b"QUERY_KEY1").await.unwrap();
+
+        let res = cache
+            .query()
+            .sort_by(QuerySort::LastAccessed)
+            .limit(2)
+            .execute()
+            .unwrap();
+        let keys = res.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys, [b"QUERY_KEY2", b"QUERY_KEY3"]);
+    }
+
+    #[tokio::test]
+    async fn query() {
+        let cache = CacheBuilder::new(test_dir("query"))
+            .meta_backend(MetaBackendKind::Memory)
+            .build()
+            .await
+            .unwrap();
+        let before = std::time::SystemTime::now();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        for (i, key) in [b"QUERY_KEY1", b"QUERY_KEY2", b"QUERY_KEY3"]
+            .iter()
+            .enumerate()
+        {
+            let opts = WriteOptions::new()
+                .attribute("index", i as i64)
+                .tag(if i == 0 { "first" } else { "rest" });
+            cache
+                .write_with(key, "x".repeat(i + 1), opts)
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        let keys = |results: Vec<(Vec<u8>, Metadata)>| -> Vec<Vec<u8>> {
+            results.into_iter().map(|(k, _)| k).collect()
+        };
+
+        let res = cache.query().min_size(2).execute().unwrap();
+        assert_eq!(res.len(), 2);
+        let res = cache
+            .query()
+            .sort_by_desc(QuerySort::Size)
+            .limit(2)
+            .execute()
+            .unwrap();
+        assert_eq!(keys(res), [b"QUERY_KEY3", b"QUERY_KEY2"]);
+        let res = cache
+            .query()
+            .sort_by(QuerySort::LastModified)
+            .limit(1)
+            .execute()
+            .unwrap();
+        assert_eq!(keys(res), [b"QUERY_KEY1"]);
+        let res = cache.query().tag("rest").max_size(2).execute().unwrap();
+        assert_eq!(keys(res), [b"QUERY_KEY2"]);
+        let res = cache.query().attribute_eq("index", 2).execute().unwrap();
+        assert_eq!(keys(res), [b"QUERY_KEY3"]);
+        let res = cache.query().modified_before(before).execute().unwrap();
+        assert!(res.is_empty());
+        let res = cache
+            .query()
+            .accessed_after(before)
+            .filter(|m| m.get_size() != 2)
+            .execute()
+            .unwrap();
+        assert_eq!(res.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn durable_write_remove() {
+        let cache = CacheBuilder::new(test_dir("durable-write-remove"))
+            .durability(Durability::DataAndDirectory)
+            .build()
+            .await
+            .unwrap();
+
+        cache
+            .write(b"DURABLE_CACHE_KEY", b"Hello World")
+            .await
+            .unwrap();
+        let data = cache.read(b"DURABLE_CACHE_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+        cache.remove(b"DURABLE_CACHE_KEY").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn temp_sweep() {
+        let path = test_dir("temp-sweep");
+        std::fs::create_dir_all(&path).unwrap();
+        let orphan = crate::tmp::tmppath_in(&path);
+        std::fs::write(&orphan, b"Hello World").unwrap();
+
+        // young temporary files must be left alone
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Memory)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(cache.startup_report().temp_files_removed, 0);
+        assert!(orphan.exists());
+        drop(cache);
+
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Memory)
+            .temp_sweep_age(Some(Duration::ZERO))
+            .build()
+            .await
+            .unwrap();
+        let report = cache.startup_report();
+        assert_eq!(report.temp_files_removed, 1);
+        assert_eq!(report.temp_bytes_reclaimed, b"Hello World".len() as u64);
+        assert!(!orphan.exists());
+    }
+
+    #[tokio::test]
+    async fn check_repair() {
+        let path = test_dir("check");
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Memory)
+            .build()
+            .await
+            .unwrap();
+        for key in [b"CHECK_KEY1", b"CHECK_KEY2", b"CHECK_KEY3", b"CHECK_KEY4"] {
+            cache.write(key, b"Hello World").await.unwrap();
+        }
+        let report = cache.check(CheckOptions::new()).await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.entries_checked, 4);
+
+        // dangling metadata, a size mismatch, an integrity failure, and an orphan file
+        std::fs::remove_file(cache.path_from_key(b"CHECK_KEY1")).unwrap();
+        std::fs::write(cache.path_from_key(b"CHECK_KEY2"), b"Hello").unwrap();
+        std::fs::write(cache.path_from_key(b"CHECK_KEY3"), b"Hello Worle").unwrap();
+        cache.meta.remove_metadata_for(b"CHECK_KEY4").unwrap();
+
+        let opts = CheckOptions::new().verify_integrity(true).repair(true);
+        let report = cache.check(opts).await.unwrap();
+        assert_eq!(report.dangling_metadata, [b"CHECK_KEY1"]);
+        assert_eq!(report.size_mismatches, [b"CHECK_KEY2"]);
+        assert_eq!(report.integrity_failures, [b"CHECK_KEY3"]);
+        assert_eq!(report.orphan_files, [cache.path_from_key(b"CHECK_KEY4")]);
+        assert!(report.repaired);
+
+        assert!(cache.check(CheckOptions::new()).await.unwrap().is_clean());
+        assert!(cache.is_empty().unwrap());
+        assert!(!cache.path_from_key(b"CHECK_KEY4").exists());
+
+        // an orphan that is written while the check waits for its lock is left alone
+        cache.write(b"CHECK_KEY5", b"Hello World").await.unwrap();
+        cache.meta.remove_metadata_for(b"CHECK_KEY5").unwrap();
+        let cache = Arc::new(cache);
+        let guard = cache.lock_key(b"CHECK_KEY5").await;
+        let check = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move { cache.check(CheckOptions::new().repair(true)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        guard.write(b"Goodbye World").await.unwrap();
+        drop(guard);
+        check.await.unwrap().unwrap();
+        let data = cache.read(b"CHECK_KEY5").await.unwrap();
+        assert_eq!(data.as_ref(), b"Goodbye World");
+    }
+
+    #[test]
+    fn corruption_detection() {
+        let io_err = |kind| io::Error::from(kind);
+        assert!(rebuild::is_corruption(&ForcepError::MetaDb(
+            sled::Error::Io(io_err(io::ErrorKind::InvalidData))
+        )));
+        // a database that is merely locked or inaccessible must never be replaced
+        for kind in [io::ErrorKind::WouldBlock, io::ErrorKind::PermissionDenied] {
+            assert!(!rebuild::is_corruption(&ForcepError::MetaDb(
+                sled::Error::Io(io_err(kind))
+            )));
+            assert!(!rebuild::is_corruption(&ForcepError::Backend(Box::new(
+                io_err(kind)
+            ))));
+        }
+        #[cfg(feature = "redb")]
+        {
+            let backend = |e: redb::Error| ForcepError::Backend(Box::new(e));
+            assert!(rebuild::is_corruption(&backend(redb::Error::Corrupted(
+                String::new()
+            ))));
+            assert!(!rebuild::is_corruption(&backend(
+                redb::Error::DatabaseAlreadyOpen
+            )));
+            assert!(!rebuild::is_corruption(&backend(redb::Error::Io(io_err(
+                io::ErrorKind::WouldBlock
+            )))));
+        }
+    }
+
+    #[tokio::test]
+    async fn rebuild_index() {
+        let path = test_dir("rebuild");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"REBUILD_KEY1", b"Hello World").await.unwrap();
+        cache
+            .write_with(b"REBUILD_KEY2", b"Hello", WriteOptions::new().tag("lost"))
+            .await
+            .unwrap();
+        drop(cache);
+
+        // corrupt the index, so it can't be opened anymore
+        std::fs::write(path.join("index/conf"), "not a sled configuration file").unwrap();
+        assert!(CacheBuilder::new(&path).build().await.is_err());
+
+        let cache = CacheBuilder::new(&path)
+            .rebuild_index_on_failure(true)
+            .build()
+            .await
+            .unwrap();
+        let report = cache.startup_report();
+        assert!(report.corrupt_index.as_ref().unwrap().exists());
+        assert_eq!(report.entries_restored, 2);
+        assert_eq!(cache.len().unwrap(), 2);
+        assert_eq!(cache.total_size().unwrap(), 16);
+        let meta = cache.read_metadata(b"REBUILD_KEY2").unwrap();
+        assert!(meta.get_tags().is_empty());
+        let data = cache.read(b"REBUILD_KEY1").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+
+        // nothing left to restore
+        assert_eq!(cache.rebuild_index().await.unwrap(), 0);
+
+        // an orphan that is removed while the rebuild waits for its lock isn't restored
+        cache.write(b"REBUILD_KEY3", b"Hello World").await.unwrap();
+        cache.meta.remove_metadata_for(b"REBUILD_KEY3").unwrap();
+        let cache = Arc::new(cache);
+        let (locked, wait_locked) = tokio::sync::oneshot::channel();
+        let remover = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move {
+                let _guard = cache.lock_key(b"REBUILD_KEY3").await;
+                locked.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                std::fs::remove_file(cache.path_from_key(b"REBUILD_KEY3")).unwrap();
+            }
+        });
+        wait_locked.await.unwrap();
+        assert_eq!(cache.rebuild_index().await.unwrap(), 0);
+        remover.await.unwrap();
+        assert!(cache.read_metadata(b"REBUILD_KEY3").is_err());
+    }
+
+    #[tokio::test]
+    async fn scrub() {
+        let path = test_dir("scrub");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"SCRUB_GOOD", b"Hello World").await.unwrap();
+        cache.write(b"SCRUB_BAD", b"Hello World").await.unwrap();
+        // same size, different data
+        std::fs::write(cache.path_from_key(b"SCRUB_BAD"), b"Hello W0rld").unwrap();
+        drop(cache);
+
+        let cache = CacheBuilder::new(&path)
+            .scrub(
+                ScrubOptions::new()
+                    .max_bytes_per_sec(None)
+                    .action(ScrubAction::Remove),
+            )
+            .build()
+            .await
+            .unwrap();
+        let mut status = cache.scrub_status().unwrap();
+        for _ in 0..100 {
+            if status.passes_completed > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            status = cache.scrub_status().unwrap();
+        }
+        assert_eq!(status.passes_completed, 1);
+        assert_eq!(status.pass_total, 2);
+        assert_eq!(status.corrupted, vec![b"SCRUB_BAD".to_vec()]);
+        assert_eq!(status.entries_removed, 1);
+        assert!(matches!(
+            cache.read(b"SCRUB_BAD").await,
+            Err(ForcepError::MetaNotFound)
+        ));
+        // the removal went through the journal like any other
+        assert!(!cache.path_from_key(b"SCRUB_BAD").exists());
+        assert!(cache.meta.intents().unwrap().is_empty());
+        let data = cache.read(b"SCRUB_GOOD").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+    }
+
+    #[cfg(not(feature = "sha256"))]
+    #[tokio::test]
+    async fn unverifiable_integrity() {
+        let path = test_dir("unverifiable-integrity");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"UNVERIFIABLE", b"Hello World").await.unwrap();
+        // pretend the entry was written by a build with the `sha256` feature
+        let written = Metadata::new(
+            b"Hello World",
+            HashAlgorithm::Sha256,
+            Attributes::new(),
+            vec![],
+        );
+        cache.meta.remove_metadata_for(b"UNVERIFIABLE").unwrap();
+        assert!(
+            cache
+                .meta
+                .restore_metadata(b"UNVERIFIABLE", &written)
+                .unwrap()
+        );
+
+        let check = CheckOptions::new().verify_integrity(true).repair(true);
+        let report = cache.check(check).await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.unverifiable, [b"UNVERIFIABLE"]);
+        drop(cache);
+
+        for action in [ScrubAction::Remove, ScrubAction::Quarantine] {
+            let cache = CacheBuilder::new(&path)
+                .scrub(ScrubOptions::new().max_bytes_per_sec(None).action(action))
+                .build()
+                .await
+                .unwrap();
+            let mut status = cache.scrub_status().unwrap();
+            for _ in 0..100 {
+                if status.passes_completed > 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                status = cache.scrub_status().unwrap();
+            }
+            assert_eq!(status.passes_completed, 1);
+            assert!(status.corrupted.is_empty());
+            assert_eq!(status.unverifiable, 1);
+            let data = cache.read(b"UNVERIFIABLE").await.unwrap();
+            assert_eq!(data.as_ref(), b"Hello World");
+        }
+    }
+
+    #[tokio::test]
+    async fn quarantine() {
+        let path = test_dir("quarantine");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        let opts = WriteOptions::new().attribute("origin", "test").tag("q");
+        let written = cache
+            .write_with(b"Q_BAD", b"Hello World", opts)
+            .await
+            .unwrap();
+        std::fs::write(cache.path_from_key(b"Q_BAD"), b"Hello W0rld").unwrap();
+
+        let check = CheckOptions::new()
+            .verify_integrity(true)
+            .repair(true)
+            .quarantine(true);
+        let report = cache.check(check.clone()).await.unwrap();
+        assert_eq!(report.quarantined.len(), 1);
+        assert!(cache.read(b"Q_BAD").await.is_err());
+        assert!(cache.check(CheckOptions::new()).await.unwrap().is_clean());
+
+        let entries = cache.quarantined().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.id, report.quarantined[0]);
+        assert_eq!(entry.key, b"Q_BAD");
+        assert_eq!(entry.metadata, written);
+        assert_ne!(entry.detected_integrity, written.get_integrity_digest());
+        assert_eq!(std::fs::read(&entry.path).unwrap(), b"Hello W0rld");
+
+        // restoring accepts the data as it is, keeping the attributes and tags
+        let restored = cache.restore_quarantined(&entry.id).await.unwrap();
+        assert!(restored.check_integrity_of(b"Hello W0rld"));
+        assert_eq!(restored.get_attributes(), written.get_attributes());
+        assert!(restored.has_tag("q"));
+        assert_eq!(cache.read(b"Q_BAD").await.unwrap().as_ref(), b"Hello W0rld");
+        assert!(cache.quarantined().await.unwrap().is_empty());
+        assert!(matches!(
+            cache.restore_quarantined(&entry.id).await,
+            Err(ForcepError::NotFound)
+        ));
+        assert!(matches!(
+            cache.purge_quarantined("../../Q_BAD").await,
+            Err(ForcepError::NotFound)
+        ));
+
+        // the scrubber can quarantine entries as well
+        std::fs::write(cache.path_from_key(b"Q_BAD"), b"Hello World").unwrap();
+        drop(cache);
+        let cache = CacheBuilder::new(&path)
+            .scrub(
+                ScrubOptions::new()
+                    .max_bytes_per_sec(None)
+                    .action(ScrubAction::Quarantine),
+            )
+            .build()
+            .await
+            .unwrap();
+        let mut status = cache.scrub_status().unwrap();
+        for _ in 0..100 {
+            if status.passes_completed > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            status = cache.scrub_status().unwrap();
+        }
+        assert_eq!(status.entries_quarantined, 1);
+        assert_eq!(cache.quarantined().await.unwrap().len(), 1);
+        assert_eq!(cache.purge_quarantine().await.unwrap(), 1);
+        assert!(cache.quarantined().await.unwrap().is_empty());
+        assert!(cache.check(check).await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn quarantine_replay() {
+        let path = test_dir("quarantine-replay");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"Q_UNMOVED", b"Hello World").await.unwrap();
+        cache.write(b"Q_MOVED", b"Hello World").await.unwrap();
+        let dir = path.join("quarantine");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // interrupted after writing the record, before moving the data
+        std::fs::write(dir.join("aaaaaaaaaaaaaaaa.meta"), b"record").unwrap();
+        cache
+            .meta
+            .begin_intent(&Intent::Quarantine {
+                key: b"Q_UNMOVED".to_vec(),
+                id: "aaaaaaaaaaaaaaaa".to_owned(),
+            })
+            .unwrap();
+        // interrupted after moving the data, before removing the metadata
+        std::fs::write(dir.join("bbbbbbbbbbbbbbbb.meta"), b"record").unwrap();
+        std::fs::rename(
+            cache.path_from_key(b"Q_MOVED"),
+            dir.join("bbbbbbbbbbbbbbbb"),
+        )
+        .unwrap();
+        cache
+            .meta
+            .begin_intent(&Intent::Quarantine {
+                key: b"Q_MOVED".to_vec(),
+                id: "bbbbbbbbbbbbbbbb".to_owned(),
+            })
+            .unwrap();
+        // a record whose data was deleted by hand
+        std::fs::write(dir.join("cccccccccccccccc.meta"), b"record").unwrap();
+        drop(cache);
+
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        assert_eq!(cache.startup_report().intents_replayed, 2);
+        assert_eq!(
+            cache.read(b"Q_UNMOVED").await.unwrap().as_ref(),
+            b"Hello World"
+        );
+        assert!(!dir.join("aaaaaaaaaaaaaaaa.meta").exists());
+        assert!(cache.read_metadata(b"Q_MOVED").is_err());
+        assert!(dir.join("bbbbbbbbbbbbbbbb").exists());
+
+        // records without data are purged, but not counted
+        assert_eq!(cache.purge_quarantine().await.unwrap(), 1);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn storage_full() {
+        use std::error::Error;
+
+        // writes to `/dev/full` always fail with `ENOSPC`
+        let e = std::fs::write("/dev/full", b"Hello World").unwrap_err();
+        let e = super::storage_full(ForcepError::Io(e));
+        assert!(matches!(e, ForcepError::StorageFull(_)));
+        assert!(e.source().is_some());
+
+        // the metadata backends report it as well
+        let e = std::fs::write("/dev/full", b"Hello World").unwrap_err();
+        let e = super::storage_full(ForcepError::MetaDb(sled::Error::Io(e)));
+        assert!(matches!(e, ForcepError::StorageFull(_)));
+
+        let e = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            super::storage_full(ForcepError::Io(e)),
+            ForcepError::Io(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn transaction() {
+        let path = test_dir("transaction");
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Memory)
+            .build()
+            .await
+            .unwrap();
+        cache.write(b"TX_OLD", b"old").await.unwrap();
+
+        let results = cache
+            .transaction()
+            .write(b"TX_BLOB", b"Hello World")
+            .write(b"TX_MANIFEST", b"TX_BLOB")
+            .write(b"TX_MANIFEST", b"TX_BLOB TX_BLOB")
+            .remove(b"TX_OLD")
+            .commit()
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[2].get_version(), 2);
+        assert_eq!(results[3].get_size(), 3);
+        assert_eq!(cache.len().unwrap(), 2);
+        assert_eq!(cache.total_size().unwrap(), 26);
+        let data = cache.read(b"TX_MANIFEST").await.unwrap();
+        assert_eq!(data.as_ref(), b"TX_BLOB TX_BLOB");
+        assert!(cache.read(b"TX_OLD").await.is_err());
+
+        // a failing operation rolls back every other one
+        let res = cache
+            .transaction()
+            .write(b"TX_BLOB", b"Goodbye World")
+            .write(b"TX_NEW", b"new")
+            .write_with(b"TX_MANIFEST", b"", WriteOptions::new().if_version(1))
+            .commit()
+            .await;
+        assert!(matches!(
+            res,
+            Err(ForcepError::VersionMismatch {
+                expected: 1,
+                actual: Some(2)
+            })
+        ));
+        let res = cache.transaction().remove(b"TX_OLD").commit().await;
+        assert!(matches!(res, Err(ForcepError::MetaNotFound)));
+        let data = cache.read(b"TX_BLOB").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+        assert!(!cache.path_from_key(b"TX_NEW").exists());
+        assert_eq!(cache.len().unwrap(), 2);
+
+        // no staged or backup files are left behind
+        let leftover = std::fs::read_dir(&path)
+            .unwrap()
+            .filter(|e| crate::tmp::is_tmpname(e.as_ref().unwrap().file_name().to_str().unwrap()))
+            .count();
+        assert_eq!(leftover, 0);
+        assert!(cache.meta.intents().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transaction_replay() {
+        let path = test_dir("transaction-replay");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        for key in [b"TX_PLACED", b"TX_STAGED", b"TX_REMOVE"] {
+            cache.write(key, b"old").await.unwrap();
+        }
+        let (placed, staged, removed) = (
+            cache.path_from_key(b"TX_PLACED"),
+            cache.path_from_key(b"TX_STAGED"),
+            cache.path_from_key(b"TX_REMOVE"),
+        );
+        let change = |key: &[u8], tmp: Option<&str>, backup: &str| crate::TxChange {
+            key: key.to_vec(),
+            tmp: tmp.map(str::to_owned),
+            backup: backup.to_owned(),
+        };
+        cache
+            .meta
+            .begin_intent(&Intent::Transaction {
+                changes: vec![
+                    change(b"TX_PLACED", Some("tmp0000000000"), "tmp1111111111"),
+                    change(b"TX_STAGED", Some("tmp2222222222"), "tmp3333333333"),
+                    change(b"TX_REMOVE", None, "tmp4444444444"),
+                ],
+            })
+            .unwrap();
+        // interrupted after the first change was made, and the previous file of the last one was
+        // moved aside
+        std::fs::rename(&placed, path.join("tmp1111111111")).unwrap();
+        std::fs::write(&placed, b"new").unwrap();
+        std::fs::write(path.join("tmp2222222222"), b"new").unwrap();
+        std::fs::rename(&removed, path.join("tmp4444444444")).unwrap();
+        drop(cache);
+
+        // the backups must be restored before temporary files are swept
+        let cache = CacheBuilder::new(&path)
+            .temp_sweep_age(Some(Duration::ZERO))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(cache.startup_report().intents_replayed, 1);
+        for key in [b"TX_PLACED", b"TX_STAGED", b"TX_REMOVE"] {
+            assert_eq!(cache.read(key).await.unwrap().as_ref(), b"old");
+        }
+        assert!(staged.exists());
+        let leftover = std::fs::read_dir(&path)
+            .unwrap()
+            .filter(|e| crate::tmp::is_tmpname(e.as_ref().unwrap().file_name().to_str().unwrap()))
+            .count();
+        assert_eq!(leftover, 0);
+        assert!(cache.meta.intents().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn key_lock() {
+        let path = test_dir("key-lock");
+        let cache = Arc::new(
+            CacheBuilder::new(&path)
+                .meta_backend(MetaBackendKind::Memory)
+                .build()
+                .await
+                .unwrap(),
+        );
+
+        // read-modify-write sequences under the lock never lose an update
+        let tasks = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for _ in 0..5 {
+                        let guard = cache.lock_key(b"COUNTER").await;
+                        let count = match cache.read(b"COUNTER").await {
+                            Ok(data) => u64::from_le_bytes(data.as_ref().try_into().unwrap()),
+                            Err(_) => 0,
+                        };
+                        guard.write((count + 1).to_le_bytes()).await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let data = cache.read(b"COUNTER").await.unwrap();
+        assert_eq!(u64::from_le_bytes(data.as_ref().try_into().unwrap()), 40);
+
+        // concurrent writes and removals leave the metadata describing the file on disk
+        let tasks = (0..8u8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for _ in 0..10 {
+                        if i % 2 == 0 {
+                            cache.write(b"CONTENDED", [i; 64]).await.unwrap();
+                        } else {
+                            let _ = cache.remove(b"CONTENDED").await;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let file = std::fs::read(cache.path_from_key(b"CONTENDED")).ok();
+        match cache.read_metadata(b"CONTENDED") {
+            Ok(meta) => assert!(meta.check_integrity_of(&file.unwrap())),
+            Err(_) => assert!(file.is_none()),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn key_lock_guard() {
+        use crate::evictors::FifoEvictor;
+        let path = test_dir("key-lock-guard");
+        let cache = Arc::new(
+            CacheBuilder::new(&path)
+                .meta_backend(MetaBackendKind::Memory)
+                .build()
+                .await
+                .unwrap(),
+        );
+        const TIMEOUT: Duration = Duration::from_secs(10);
+
+        // the task holding the guard can change every other entry (sharing its stripe or not),
+        // and evictions skip the locked entry instead of waiting for it
+        let guard = cache.lock_key(b"GUARDED").await;
+        guard.write(b"Hello World").await.unwrap();
+        let changes = async {
+            for i in 0..512u32 {
+                cache.write(i.to_le_bytes(), b"other").await.unwrap();
+            }
+            cache
+                .transaction()
+                .write(b"GUARDED_TX", b"tx")
+                .remove(0u32.to_le_bytes())
+                .commit()
+                .await
+                .unwrap();
+            cache.remove(1u32.to_le_bytes()).await.unwrap();
+            cache.evict_with(FifoEvictor::new(0)).await.unwrap();
+        };
+        tokio::time::timeout(TIMEOUT, changes).await.unwrap();
+        assert_eq!(cache.len().unwrap(), 1);
+        let data = cache.read(b"GUARDED").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+
+        // a transaction waiting for the guard doesn't hold up other keys in the meantime
+        let keys = (0..64u32).map(u32::to_le_bytes).collect::<Vec<_>>();
+        let tx = tokio::spawn({
+            let (cache, keys) = (Arc::clone(&cache), keys.clone());
+            async move {
+                let tx = keys
+                    .iter()
+                    .fold(cache.transaction(), |tx, key| tx.write(key, b"tx"));
+                tx.write(b"GUARDED", b"tx").commit().await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let writes = async {
+            for key in &keys {
+                cache.write(key, b"guarded task").await.unwrap();
+            }
+        };
+        tokio::time::timeout(TIMEOUT, writes).await.unwrap();
+        assert!(!tx.is_finished());
+
+        // other writers of the entry wait until the guard is dropped
+        let writer = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move { cache.write(b"GUARDED", b"other task").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!writer.is_finished());
+        drop(guard);
+        tokio::time::timeout(TIMEOUT, async {
+            tx.await.unwrap().unwrap();
+            writer.await.unwrap().unwrap();
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dir_lock() {
+        let path = test_dir("dir-lock");
+        let builder = CacheBuilder::new(&path).meta_backend(MetaBackendKind::Memory);
+        let cache = builder.clone().build().await.unwrap();
+        let res = builder.clone().build().await;
+        assert!(matches!(
+            res,
+            Err(ForcepError::Locked { pid: Some(pid) }) if pid == std::process::id()
+        ));
+
+        // waiting succeeds once the first cache is dropped
+        let waiting = builder
+            .clone()
+            .lock_mode(LockMode::Wait(Duration::from_secs(10)))
+            .build();
+        let (res, _) = tokio::join!(waiting, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(cache);
+        });
+        let cache = res.unwrap();
+        let res = builder
+            .lock_mode(LockMode::Wait(Duration::from_millis(100)))
+            .build()
+            .await;
+        assert!(matches!(res, Err(ForcepError::Locked { .. })));
+        drop(cache);
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let path = test_dir("read-only");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"RO_KEY", b"Hello World").await.unwrap();
+        cache.flush().unwrap();
+
+        // the directory is still locked by `cache`
+        let ro = CacheBuilder::new(&path)
+            .read_only(true)
+            .track_access(true)
+            .build()
+            .await
+            .unwrap();
+        let data = ro.read(b"RO_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+        assert_eq!(ro.read_metadata(b"RO_KEY").unwrap().get_hits(), 0);
+        assert_eq!(ro.metadata_iter().count(), 1);
+        assert!(matches!(
+            ro.write(b"RO_KEY", b"").await,
+            Err(ForcepError::ReadOnly)
+        ));
+        assert!(matches!(
+            ro.remove(b"RO_KEY").await,
+            Err(ForcepError::ReadOnly)
+        ));
+        let evictor = crate::evictors::LruEvictor::new(0);
+        assert!(matches!(
+            ro.evict_with(evictor).await,
+            Err(ForcepError::ReadOnly)
+        ));
+
+        // changes made after opening aren't visible in the snapshot
+        assert!(ro._snapshot.is_some());
+        cache.write(b"RO_KEY2", b"Hello World").await.unwrap();
+        assert!(ro.read_metadata(b"RO_KEY2").is_err());
+        drop(ro);
+        assert!(cache.read(b"RO_KEY").await.is_ok());
+    }
+
+    #[cfg(feature = "redb")]
+    #[tokio::test]
+    async fn read_only_redb() {
+        let path = test_dir("read-only-redb");
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Redb)
+            .build()
+            .await
+            .unwrap();
+        cache.write(b"RO_KEY", b"Hello World").await.unwrap();
+        let builder = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Redb)
+            .read_only(true);
+
+        // a database that is open for writing can only be copied
+        let ro = builder.clone().build().await.unwrap();
+        assert!(ro._snapshot.is_some());
+        assert_eq!(ro.read(b"RO_KEY").await.unwrap().as_ref(), b"Hello World");
+        drop((ro, cache));
+
+        // otherwise it's opened in place, by any number of read-only caches
+        let ro = builder.clone().build().await.unwrap();
+        let other = builder.build().await.unwrap();
+        assert!(ro._snapshot.is_none() && other._snapshot.is_none());
+        assert_eq!(ro.read(b"RO_KEY").await.unwrap().as_ref(), b"Hello World");
+        assert_eq!(other.metadata_iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn journal_replay() {
+        let path = test_dir("journal");
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        cache.write(b"JOURNAL_MOVED", b"old").await.unwrap();
+        cache.write(b"JOURNAL_REMOVED", b"old").await.unwrap();
+        let new_meta =
+            |data: &[u8]| Metadata::new(data, HashAlgorithm::Md5, Attributes::new(), vec![]);
+
+        // a write that was interrupted after moving its file
+        std::fs::write(cache.path_from_key(b"JOURNAL_MOVED"), b"new").unwrap();
+        cache
+            .meta
+            .begin_intent(&Intent::Write {
+                key: b"JOURNAL_MOVED".to_vec(),
+                tmp: "tmp0000000000".to_owned(),
+                meta: new_meta(b"new"),
+            })
+            .unwrap();
+        // a write that was interrupted before moving its file
+        let staged = path.join("tmp1111111111");
+        std::fs::write(&staged, b"staged").unwrap();
+        cache
+            .meta
+            .begin_intent(&Intent::Write {
+                key: b"JOURNAL_STAGED".to_vec(),
+                tmp: "tmp1111111111".to_owned(),
+                meta: new_meta(b"staged"),
+            })
+            .unwrap();
+        // a removal that was interrupted after deleting its file
+        std::fs::remove_file(cache.path_from_key(b"JOURNAL_REMOVED")).unwrap();
+        cache
+            .meta
+            .begin_intent(&Intent::Remove {
+                key: b"JOURNAL_REMOVED".to_vec(),
+                tmp: "tmp2222222222".to_owned(),
+            })
+            .unwrap();
+        // a malformed removal that would point at the cache directory itself
+        cache
+            .meta
+            .begin_intent(&Intent::Remove {
+                key: b"JOURNAL_MALFORMED".to_vec(),
+                tmp: String::new(),
+            })
+            .unwrap();
+        drop(cache);
+
+        let cache = CacheBuilder::new(&path).build().await.unwrap();
+        assert_eq!(cache.startup_report().intents_replayed, 4);
+        assert!(path.is_dir());
+        assert_eq!(cache.len().unwrap(), 1);
+        let meta = cache.read_metadata(b"JOURNAL_MOVED").unwrap();
+        assert!(meta.check_integrity_of(b"new"));
+        assert_eq!(meta.get_version(), 2);
+        assert!(cache.read_metadata(b"JOURNAL_STAGED").is_err());
+        assert!(!staged.exists());
+        assert!(cache.read_metadata(b"JOURNAL_REMOVED").is_err());
+        assert!(cache.meta.intents().unwrap().is_empty());
+
+        // regular operations leave nothing behind
+        cache.write(b"JOURNAL_MOVED", b"newer").await.unwrap();
+        cache.remove(b"JOURNAL_MOVED").await.unwrap();
+        assert!(cache.remove(b"JOURNAL_MOVED").await.is_err());
+        assert!(cache.meta.intents().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush() {
+        let cache = CacheBuilder::new(test_dir("flush"))
+            .flush_interval(Duration::from_millis(10))
+            .build()
+            .await
+            .unwrap();
+
+        cache
+            .write(b"FLUSH_CACHE_KEY", b"Hello World")
+            .await
+            .unwrap();
+        cache.flush().unwrap();
+        cache.flush_async().await.unwrap();
+        // give the periodic flush a chance to run
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        cache.remove(b"FLUSH_CACHE_KEY").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_update_attributes() {
+        let cache = test_cache("write-update-attributes").await;
+
+        let opts = WriteOptions::new()
+            .attribute("content-type", "text/plain")
+            .attribute("producer-version", 1);
+        cache
+            .write_with(b"ATTR_CACHE_KEY", b"Hello World", opts)
+            .await
+            .unwrap();
+        cache
+            .update_attributes(b"ATTR_CACHE_KEY", |attrs| {
+                attrs.insert("producer-version".to_owned(), 2.into());
+            })
+            .unwrap();
+
+        let meta = cache.read_metadata(b"ATTR_CACHE_KEY").unwrap();
+        let attrs = meta.get_attributes();
+        assert_eq!(attrs["content-type"].as_str(), Some("text/plain"));
+        assert_eq!(attrs["producer-version"].as_int(), Some(2));
+        // the value should remain untouched by the attribute update
+        let data = cache.read(b"ATTR_CACHE_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+    }
+
+    #[tokio::test]
+    async fn tag_invalidation() {
+        let cache = test_cache("tag-invalidation").await;
+
+        let opts = WriteOptions::new().tag("tag-test-rev").tag("tag-test-all");
+        cache
+            .write_with(b"TAG_CACHE_KEY1", b"Hello World", opts.clone())
+            .await
+            .unwrap();
+        cache
+            .write_with(b"TAG_CACHE_KEY2", b"Hello World", opts)
+            .await
+            .unwrap();
+        cache
+            .write_with(
+                b"TAG_CACHE_KEY3",
+                b"Hello World",
+                WriteOptions::new().tag("tag-test-all"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.keys_with_tag("tag-test-rev").count(), 2);
+
+        assert_eq!(cache.invalidate_tag("tag-test-rev").await.unwrap(), 2);
+        assert!(cache.read(b"TAG_CACHE_KEY1").await.is_err());
+        assert!(cache.read(b"TAG_CACHE_KEY2").await.is_err());
+        assert_eq!(cache.keys_with_tag("tag-test-rev").count(), 0);
+        // removed entries should have also been dropped from their other tags
+        let keys: Vec<_> = cache
+            .keys_with_tag("tag-test-all")
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(keys, vec![b"TAG_CACHE_KEY3".to_vec()]);
+
+        // entries whose file is already gone only have their metadata cleaned up
+        std::fs::remove_file(cache.path_from_key(b"TAG_CACHE_KEY3")).unwrap();
+        assert_eq!(cache.invalidate_tag("tag-test-all").await.unwrap(), 0);
+        assert!(matches!(
+            cache.read_metadata(b"TAG_CACHE_KEY3"),
+            Err(ForcepError::MetaNotFound)
+        ));
+        assert!(cache.check(CheckOptions::new()).await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn memory_backend() {
+        let cache = CacheBuilder::new(test_dir("memory-backend"))
+            .meta_backend(MetaBackendKind::Memory)
+            .build()
+            .await
+            .unwrap();
+
+        cache.write(b"CACHE_KEY", b"Hello World").await.unwrap();
+        let data = cache.read(b"CACHE_KEY").await.unwrap();
+        assert_eq!(data.as_ref(), b"Hello World");
+        assert_eq!(cache.metadata_iter().count(), 1);
+        assert_eq!(cache.len().unwrap(), 1);
+        assert_eq!(cache.total_size().unwrap(), b"Hello World".len() as u64);
+
+        // overwriting shouldn't count the entry twice
+        cache.write(b"CACHE_KEY", b"Hello").await.unwrap();
+        assert_eq!(cache.len().unwrap(), 1);
+        assert_eq!(cache.total_size().unwrap(), b"Hello".len() as u64);
+        cache.remove(b"CACHE_KEY").await.unwrap();
+        assert!(cache.is_empty().unwrap());
+        assert_eq!(cache.total_size().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn lru_eviction() {
+        use crate::evictors::LruEvictor;
+
+        let cache = CacheBuilder::new(test_dir("lru-eviction"))
+            .meta_backend(MetaBackendKind::Memory)
+            .track_access(true)
+            .build()
+            .await
+            .unwrap();
+        for key in [b"EVICT_KEY1", b"EVICT_KEY2", b"EVICT_KEY3"] {
+            cache.write(key, b"Hello World").await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        // the first entry is now the most recently used one
+        cache.read(b"EVICT_KEY1").await.unwrap();
+
+        let evicted = cache
+            .evict_with(LruEvictor::new(b"Hello World".len() as u64))
+            .await
+            .unwrap();
+        assert_eq!(evicted, 2 * b"Hello World".len() as u64);
+        assert!(cache.read_metadata(b"EVICT_KEY1").is_ok());
+        assert!(cache.read_metadata(b"EVICT_KEY2").is_err());
+        assert!(cache.read_metadata(b"EVICT_KEY3").is_err());
+        cache.remove(b"EVICT_KEY1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_coherence() {
+        use crate::evictors::FifoEvictor;
+
+        let path = test_dir("memory-coherence");
+        let cache = CacheBuilder::new(&path)
+            .meta_backend(MetaBackendKind::Memory)
+            .memory_lru_max_size(1024)
+            .build()
+            .await
+            .unwrap();
+
+        // removing an entry drops it from memory, even if its metadata comes back without a write
+        cache.write(b"MEM_KEY", b"old").await.unwrap();
+        cache.read(b"MEM_KEY").await.unwrap();
+        cache.remove(b"MEM_KEY").await.unwrap();
+        assert!(cache.mem.get(b"MEM_KEY").is_none());
+        assert!(cache.read(b"MEM_KEY").await.is_err());
+        let path = cache.path_from_key(b"MEM_KEY");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"new").unwrap();
+        assert_eq!(cache.rebuild_index().await.unwrap(), 1);
+        assert_eq!(cache.read(b"MEM_KEY").await.unwrap().as_ref(), b"new");
+
+        // writes replace the value in memory, and removals after them drop it again
+        cache.write(b"MEM_KEY", b"newer").await.unwrap();
+        assert_eq!(cache.read(b"MEM_KEY").await.unwrap().as_ref(), b"newer");
+        cache
+            .transaction()
+            .remove(b"MEM_KEY")
+            .commit()
+            .await
+            .unwrap();
+        assert!(cache.mem.get(b"MEM_KEY").is_none());
+
+        // evicted and invalidated entries are dropped as well
+        cache.write(b"MEM_EVICTED", b"data").await.unwrap();
+        let opts = WriteOptions::new().tag("mem");
+        cache
+            .write_with(b"MEM_TAGGED", b"data", opts)
+            .await
+            .unwrap();
+        cache.invalidate_tag("mem").await.unwrap();
+        assert!(cache.mem.get(b"MEM_TAGGED").is_none());
+        cache.evict_with(FifoEvictor::new(0)).await.unwrap();
+        assert!(cache.mem.get(b"MEM_EVICTED").is_none());
+        assert!(cache.read(b"MEM_EVICTED").await.is_err());
+    }
+
     #[tokio::test]
     async fn read_metadata() {
-        let cache = default_cache().await;
+        let cache = test_cache("read-metadata").await;
 
         cache.write(&b"CACHE_KEY", &b"Hello World").await.unwrap();
-        let metadata = cache.read_metadata(&b"CACHE_KEY").unwrap();
+        let metadata = cache.read_metadata(b"CACHE_KEY").unwrap();
         assert_eq!(metadata.get_size(), b"Hello World".len() as u64);
     }
 }
@@ -0,0 +1,89 @@
+use crate::{MetaDb, Metadata, PendingAccess, Result};
+use parking_lot::Mutex;
+use std::collections::{HashMap, hash_map::Entry};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Buffers access tracking updates in memory so they can be written to the metadata database in
+/// batches, instead of on every read.
+///
+/// Updates of the same entry are merged, so the buffer only ever holds one update per key.
+#[derive(Debug, Default)]
+pub(super) struct AccessBuffer {
+    pending: Mutex<HashMap<Vec<u8>, PendingAccess>>,
+}
+
+impl AccessBuffer {
+    /// Records a single access of the entry `key` that happened just now
+    pub fn record(&self, key: &[u8]) {
+        let access = PendingAccess::now();
+        let mut pending = self.pending.lock();
+        match pending.get_mut(key) {
+            Some(existing) => existing.merge(access),
+            // only allocate the key when it isn't buffered yet
+            None => {
+                pending.insert(key.to_vec(), access);
+            }
+        }
+    }
+
+    /// Drops any buffered accesses of `key`, used when the entry is replaced or removed
+    #[inline]
+    pub fn discard(&self, key: &[u8]) {
+        self.pending.lock().remove(key);
+    }
+
+    /// Applies the buffered accesses of `key` to `meta`, so it reflects accesses that haven't
+    /// been flushed yet
+    #[inline]
+    pub fn merge_into(&self, key: &[u8], meta: &mut Metadata) {
+        if let Some(access) = self.pending.lock().get(key) {
+            meta.apply_access(*access);
+        }
+    }
+
+    /// Writes every buffered access to `meta` in a single batch.
+    ///
+    /// If writing fails, the accesses are put back into the buffer so they can be retried on the
+    /// next flush.
+    pub fn flush(&self, meta: &MetaDb) -> Result<()> {
+        let taken = std::mem::take(&mut *self.pending.lock());
+        if taken.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = meta.apply_accesses(&taken) {
+            let mut pending = self.pending.lock();
+            for (key, access) in taken {
+                match pending.entry(key) {
+                    Entry::Occupied(mut e) => e.get_mut().merge(access),
+                    Entry::Vacant(e) => {
+                        e.insert(access);
+                    }
+                }
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Spawns a task that flushes the buffer to `meta` every `interval`.
+    ///
+    /// The task only holds weak references, so it stops by itself once the cache is dropped.
+    pub fn spawn_flusher(self: &Arc<Self>, meta: &Arc<MetaDb>, interval: Duration) {
+        let (buffer, meta) = (Arc::downgrade(self), Arc::downgrade(meta));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let (Some(buffer), Some(meta)) = (buffer.upgrade(), meta.upgrade()) else {
+                    break;
+                };
+                // errors can't be reported from here, and the accesses are kept for the next
+                // flush anyways
+                let _ = tokio::task::spawn_blocking(move || buffer.flush(&meta)).await;
+            }
+        });
+    }
+}
@@ -1,5 +1,7 @@
-use crate::Result;
+use super::{Durability, LockMode, ScrubOptions};
+use crate::{HashAlgorithm, Result, backends::MetaBackendKind, evictors::EmergencyEvictor};
 use std::path;
+use std::sync::Arc;
 
 /// A builder for the [`Cache`](super::Cache) object. Exposes APIs for configuring the initial setup of the
 /// database.
@@ -43,6 +45,17 @@ impl CacheBuilder {
             path: path.as_ref().to_owned(),
             dir_depth: 2,
             track_access: false,
+            access_flush_interval: None,
+            flush_interval: None,
+            durability: Durability::None,
+            lock_mode: LockMode::Exclusive,
+            read_only: false,
+            temp_sweep_age: Some(std::time::Duration::from_secs(60 * 60)),
+            rebuild_index_on_failure: false,
+            scrub: None,
+            emergency_evictor: None,
+            hash_alg: HashAlgorithm::Md5,
+            meta_backend: MetaBackendKind::Sled,
 
             // default to no in-mem lru
             lru_size: 0,
@@ -101,11 +114,303 @@ impl CacheBuilder {
     ///
     /// Be warned, turning this on will cause blocking metadata database calls to occur on `read`
     /// operations. This does not normally occur and can cause problems for `async` applications.
+    /// See [`buffer_access_tracking`](Self::buffer_access_tracking) to avoid these calls.
     pub fn track_access(mut self, toggle: bool) -> Self {
         self.opts.track_access = toggle;
         self
     }
 
+    /// Buffers access tracking updates in memory and writes them to the metadata database in
+    /// batches every `interval`, instead of on every `read`.
+    ///
+    /// **Default is disabled**
+    ///
+    /// Accesses of the same entry are merged, adding up their hits and keeping the newest access
+    /// time. Any remaining accesses are written when the [`Cache`](super::Cache) is dropped. This
+    /// only has an effect when [`track_access`](Self::track_access) is enabled.
+    ///
+    /// Until they are flushed, buffered accesses are only visible through
+    /// [`Cache::read_metadata`](super::Cache::read_metadata), and aren't taken into account by
+    /// eviction. Accesses that haven't been flushed are lost if the process exits without
+    /// dropping the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::CacheBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .track_access(true)
+    ///     .buffer_access_tracking(Duration::from_secs(5))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn buffer_access_tracking(mut self, interval: std::time::Duration) -> Self {
+        self.opts.access_flush_interval = Some(interval);
+        self
+    }
+
+    /// Flushes the metadata database (and any buffered access tracking) every `interval`.
+    ///
+    /// **Default is disabled**
+    ///
+    /// The metadata database may buffer writes and persist them on its own schedule, so metadata
+    /// of recent writes can be lost if the process crashes. A periodic flush limits how much can
+    /// be lost. See [`Cache::flush`](super::Cache::flush) to flush manually.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::CacheBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .flush_interval(Duration::from_secs(1))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn flush_interval(mut self, interval: std::time::Duration) -> Self {
+        self.opts.flush_interval = Some(interval);
+        self
+    }
+
+    /// Sets how much effort is put into making writes and removals of entries survive a crash or
+    /// power loss.
+    ///
+    /// **Default is [`Durability::None`]**
+    ///
+    /// See [`Durability`] for the available modes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{CacheBuilder, Durability};
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .durability(Durability::DataAndDirectory)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.opts.durability = durability;
+        self
+    }
+
+    /// Sets the minimum age of orphaned temporary files that are removed when the cache is built,
+    /// or `None` to never remove them.
+    ///
+    /// **Default is `1 hour`**
+    ///
+    /// Writes and removals use temporary files in the cache directory, which are left behind if
+    /// the process dies in the middle of the operation. Files younger than the threshold are
+    /// kept, since they may belong to an operation of another process that is still in progress.
+    /// The number of files and bytes that were reclaimed can be found in the
+    /// [`StartupReport`](super::StartupReport).
+    pub fn temp_sweep_age(mut self, age: Option<std::time::Duration>) -> Self {
+        self.opts.temp_sweep_age = age;
+        self
+    }
+
+    /// If set to `true`, a metadata database that is corrupted and can't be opened is moved
+    /// aside and rebuilt from the entries on disk, instead of failing to build the cache.
+    ///
+    /// **Default is `false`**
+    ///
+    /// The corrupted database is kept next to the new one (named `index.corrupt-<timestamp>`
+    /// for the default backend), and its location can be found in the
+    /// [`StartupReport`](super::StartupReport). See
+    /// [`Cache::rebuild_index`](super::Cache::rebuild_index) for what can be restored. This only
+    /// works for backends that are stored inside the cache directory.
+    pub fn rebuild_index_on_failure(mut self, toggle: bool) -> Self {
+        self.opts.rebuild_index_on_failure = toggle;
+        self
+    }
+
+    /// Enables a background task that slowly verifies every entry against its integrity hash,
+    /// configured by `opts`.
+    ///
+    /// **Default is disabled**
+    ///
+    /// The progress and results can be retrieved with
+    /// [`Cache::scrub_status`](super::Cache::scrub_status). The task stops when the cache is
+    /// dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{CacheBuilder, ScrubAction, ScrubOptions};
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .scrub(ScrubOptions::new().action(ScrubAction::Remove))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn scrub(mut self, opts: ScrubOptions) -> Self {
+        self.opts.scrub = Some(opts);
+        self
+    }
+
+    /// Sets an evictor that is run when a write fails because the disk is full, after which the
+    /// write is retried once.
+    ///
+    /// **Default is `None`**
+    ///
+    /// Without an emergency evictor, or if the retry fails as well, the write fails with
+    /// [`ForcepError::StorageFull`](crate::ForcepError::StorageFull). Either way, the partially
+    /// written data is cleaned up. Entries locked by a [`KeyGuard`](crate::KeyGuard) are never
+    /// evicted, so writes made while holding a guard can run the evictor as well.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{CacheBuilder, evictors::LruEvictor};
+    ///
+    /// const MIN_SIZE: u64 = 512 * 1024 * 1024; // 512MiB
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .emergency_evictor(LruEvictor::new(MIN_SIZE))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn emergency_evictor<E: EmergencyEvictor>(mut self, evictor: E) -> Self {
+        self.opts.emergency_evictor = Some(Arc::new(evictor));
+        self
+    }
+
+    /// Sets how the lock on the cache directory is acquired when building the cache, see
+    /// [`LockMode`].
+    ///
+    /// **Default is [`LockMode::Exclusive`]**
+    ///
+    /// If the directory is locked by another cache (in this or another process), building fails
+    /// with [`ForcepError::Locked`](crate::ForcepError::Locked).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{CacheBuilder, LockMode};
+    /// use std::time::Duration;
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .lock_mode(LockMode::Wait(Duration::from_secs(5)))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn lock_mode(mut self, mode: LockMode) -> Self {
+        self.opts.lock_mode = mode;
+        self
+    }
+
+    /// If set to `true`, the cache is opened in read-only mode, which allows inspecting a cache
+    /// that is in use by another [`Cache`](super::Cache).
+    ///
+    /// **Default is `false`**
+    ///
+    /// A read-only cache doesn't lock the cache directory. Backends with a read-only mode
+    /// ([`Redb`](crate::backends::MetaBackendKind::Redb)) are opened in that mode when no other
+    /// cache has them open for writing. Otherwise it works on a private snapshot of the metadata
+    /// database taken when it's built, which costs a full copy of the database and fails if the
+    /// database keeps changing while it's copied. Entries written or removed by other caches
+    /// after a snapshot was taken are not reflected in its metadata. Reads and metadata lookups
+    /// work as usual, but access tracking is disabled, and every operation that changes the cache
+    /// (such as [`write`](super::Cache::write), [`remove`](super::Cache::remove), or eviction)
+    /// fails with [`ForcepError::ReadOnly`](crate::ForcepError::ReadOnly).
+    ///
+    /// The cache directory isn't created or cleaned up, and
+    /// [custom backends](crate::backends::MetaBackendKind::Custom) are used as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::CacheBuilder;
+    ///
+    /// # let cache = CacheBuilder::new("./cache").build().await.unwrap();
+    /// let inspector = CacheBuilder::new("./cache")
+    ///     .read_only(true)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// for entry in inspector.metadata_iter() {
+    ///     let (key, meta) = entry.unwrap();
+    ///     println!("{key:?}: {} bytes", meta.get_size());
+    /// }
+    /// # }
+    /// ```
+    pub fn read_only(mut self, toggle: bool) -> Self {
+        self.opts.read_only = toggle;
+        self
+    }
+
+    /// Sets the [`HashAlgorithm`] used to compute the integrity of newly written entries.
+    ///
+    /// **Default is [`HashAlgorithm::Md5`]**
+    ///
+    /// Every entry remembers the algorithm it was written with, so changing this on an existing
+    /// cache is safe: old entries keep being verified with their original algorithm. Algorithms
+    /// other than `md5` require their cargo feature to be enabled, otherwise
+    /// [`build`](Self::build) will fail with
+    /// [`ForcepError::HashUnavailable`](crate::ForcepError::HashUnavailable).
+    pub fn integrity_algorithm(mut self, alg: HashAlgorithm) -> Self {
+        self.opts.hash_alg = alg;
+        self
+    }
+
+    /// Selects the [`MetaBackend`](crate::backends::MetaBackend) that entry metadata is stored
+    /// in.
+    ///
+    /// **Default is [`MetaBackendKind::Sled`]**
+    ///
+    /// # Breaking Warning
+    ///
+    /// Backends don't share their storage, so switching the backend of an existing cache will
+    /// make all of its entries inaccessible.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{CacheBuilder, backends::MetaBackendKind};
+    ///
+    /// let cache = CacheBuilder::new("./cache")
+    ///     .meta_backend(MetaBackendKind::Memory)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn meta_backend(mut self, backend: MetaBackendKind) -> Self {
+        self.opts.meta_backend = backend;
+        self
+    }
+
     /// Builds the new [`Cache`](super::Cache) instance using the configured options of the builder.
     ///
     /// # Examples
@@ -0,0 +1,256 @@
+use super::{Cache, quarantine, scrub};
+use crate::{ForcepError, Result};
+use std::collections::HashSet;
+use std::io;
+use std::path;
+use tokio::fs as afs;
+
+/// Options for a consistency check of a [`Cache`], see [`Cache::check`].
+///
+/// # Examples
+///
+/// ```rust
+/// use forceps::CheckOptions;
+///
+/// let opts = CheckOptions::new()
+///     .verify_integrity(true)
+///     .repair(true);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    verify_integrity: bool,
+    repair: bool,
+    quarantine: bool,
+}
+
+impl CheckOptions {
+    /// Creates a new [`CheckOptions`] that only reports problems that can be found without
+    /// reading the entries.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If set to `true`, every entry is read and checked against its integrity hash.
+    ///
+    /// **Default is `false`**
+    ///
+    /// This requires reading the entire cache from disk, so it can take a long time. Entries
+    /// hashed with an algorithm that isn't [available](crate::HashAlgorithm::is_available) in
+    /// this build can't be verified, and are reported in [`CheckReport::unverifiable`] instead.
+    pub fn verify_integrity(mut self, toggle: bool) -> Self {
+        self.verify_integrity = toggle;
+        self
+    }
+
+    /// If set to `true`, the problems that are found are also repaired.
+    ///
+    /// **Default is `false`**
+    ///
+    /// * Orphan files are deleted.
+    /// * Dangling metadata is removed.
+    /// * Entries with a size mismatch or integrity failure are removed entirely, since their data
+    ///   can't be trusted.
+    pub fn repair(mut self, toggle: bool) -> Self {
+        self.repair = toggle;
+        self
+    }
+
+    /// If set to `true`, repairing moves entries with a size mismatch or integrity failure to the
+    /// quarantine area of the cache instead of removing them, so their data can still be
+    /// inspected. See [`Cache::quarantined`].
+    ///
+    /// **Default is `false`**
+    ///
+    /// This has no effect unless [`repair`](Self::repair) is enabled.
+    pub fn quarantine(mut self, toggle: bool) -> Self {
+        self.quarantine = toggle;
+        self
+    }
+}
+
+/// The result of a consistency check of a [`Cache`], see [`Cache::check`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CheckReport {
+    /// Number of metadata entries that were checked
+    pub entries_checked: u64,
+    /// Entry files that have no metadata
+    pub orphan_files: Vec<path::PathBuf>,
+    /// Keys of entries whose metadata exists, but whose file doesn't
+    pub dangling_metadata: Vec<Vec<u8>>,
+    /// Keys of entries whose file size doesn't match the size in their metadata
+    pub size_mismatches: Vec<Vec<u8>>,
+    /// Keys of entries whose data doesn't match their integrity hash. Only checked when
+    /// [`CheckOptions::verify_integrity`] is enabled.
+    pub integrity_failures: Vec<Vec<u8>>,
+    /// Keys of entries whose integrity hash couldn't be verified, because their algorithm isn't
+    /// [available](crate::HashAlgorithm::is_available) in this build. These are never repaired.
+    pub unverifiable: Vec<Vec<u8>>,
+    /// Ids of the entries that were moved to the quarantine area while repairing, see
+    /// [`CheckOptions::quarantine`]
+    pub quarantined: Vec<String>,
+    /// Whether the problems were repaired, see [`CheckOptions::repair`]
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// Whether no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.orphan_files.is_empty()
+            && self.dangling_metadata.is_empty()
+            && self.size_mismatches.is_empty()
+            && self.integrity_failures.is_empty()
+    }
+}
+
+/// Whether `name` is a directory name created by [`Cache::path_from_key`]
+fn is_key_dir(name: &str) -> bool {
+    name == "__" || (name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Maps an I/O error to `None` if the file doesn't exist
+fn not_found_as_none<T>(res: io::Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ForcepError::Io(e)),
+    }
+}
+
+/// Finds every entry file in the directory tree of `cache`, returning the key and path of each.
+///
+/// Only files at the exact locations [`Cache::path_from_key`] produces are considered entry
+/// files, so anything else in the cache directory (like the metadata database) is left alone.
+pub(super) async fn entry_files(cache: &Cache) -> Result<Vec<(Vec<u8>, path::PathBuf)>> {
+    let mut files = Vec::new();
+    let mut stack = vec![(cache.opts.path.clone(), 0)];
+    while let Some((dir, depth)) = stack.pop() {
+        let mut entries = match not_found_as_none(afs::read_dir(&dir).await)? {
+            Some(entries) => entries,
+            None => continue,
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(ForcepError::Io)? {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let Some(file_type) = not_found_as_none(entry.file_type().await)? else {
+                continue;
+            };
+
+            if depth < cache.opts.dir_depth {
+                if file_type.is_dir() && is_key_dir(name) {
+                    stack.push((entry.path(), depth + 1));
+                }
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            // the file must be exactly where its key would be stored
+            if let Ok(key) = hex::decode(name)
+                && cache.path_from_key(&key) == entry.path()
+            {
+                files.push((key, entry.path()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Runs a consistency check of `cache`, see [`Cache::check`].
+pub(super) async fn run(cache: &Cache, opts: CheckOptions) -> Result<CheckReport> {
+    if opts.repair {
+        cache.ensure_writable()?;
+    }
+    let mut report = CheckReport::default();
+
+    // entries whose data is bad and should be removed entirely when repairing, along with the
+    // version that was checked
+    let mut corrupt = Vec::new();
+    let mut with_meta = HashSet::new();
+    for result in cache.metadata_iter() {
+        let (key, meta) = result?;
+        report.entries_checked += 1;
+        let path = cache.path_from_key(&key);
+        with_meta.insert(path.clone());
+
+        let Some(file_meta) = not_found_as_none(afs::metadata(&path).await)? else {
+            report.dangling_metadata.push(key);
+            continue;
+        };
+        if file_meta.len() != meta.get_size() {
+            report.size_mismatches.push(key.clone());
+            corrupt.push((key, meta.get_version()));
+            continue;
+        }
+        if opts.verify_integrity {
+            if !meta.get_integrity_algorithm().is_available() {
+                report.unverifiable.push(key);
+                continue;
+            }
+            let alg = meta.get_integrity_algorithm();
+            let Some((_, digest)) = scrub::digest_file(&path, alg).await? else {
+                report.dangling_metadata.push(key);
+                continue;
+            };
+            if digest.as_deref() != Some(meta.get_integrity_digest()) {
+                report.integrity_failures.push(key.clone());
+                corrupt.push((key, meta.get_version()));
+            }
+        }
+    }
+
+    let mut orphans = Vec::new();
+    for (key, path) in entry_files(cache).await? {
+        if !with_meta.contains(&path) {
+            report.orphan_files.push(path);
+            orphans.push(key);
+        }
+    }
+
+    if opts.repair {
+        // every entry is locked and checked again before it's repaired, since it may have been
+        // written or removed since it was checked
+        for key in &orphans {
+            let _guard = cache.locks.lock(key).await;
+            if cache.meta.get_metadata_opt(key)?.is_none() {
+                not_found_as_none(afs::remove_file(cache.path_from_key(key)).await)?;
+            }
+        }
+        for key in &report.dangling_metadata {
+            let _guard = cache.locks.lock(key).await;
+            if not_found_as_none(afs::metadata(cache.path_from_key(key)).await)?.is_some() {
+                continue;
+            }
+            cache.mem.remove(key);
+            match cache.meta.remove_metadata_for(key) {
+                Ok(_) | Err(ForcepError::MetaNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for (key, version) in &corrupt {
+            let _guard = cache.locks.lock(key).await;
+            let Some(meta) = cache.meta.get_metadata_opt(key)? else {
+                continue;
+            };
+            if meta.get_version() != *version {
+                continue;
+            }
+            if opts.quarantine {
+                let access = cache.access.as_deref();
+                let id =
+                    quarantine::move_in(&cache.meta, &cache.mem, access, &cache.opts, key, &meta);
+                report.quarantined.extend(id.await?);
+                continue;
+            }
+            match cache.remove_locked(key).await {
+                Ok(_) | Err(ForcepError::NotFound) | Err(ForcepError::MetaNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        report.repaired = true;
+    }
+    Ok(report)
+}
@@ -0,0 +1,66 @@
+use crate::{ForcepError, Result};
+use std::path;
+use tokio::fs as afs;
+
+/// How much effort the [`Cache`](super::Cache) puts into making writes and removals survive a
+/// crash or power loss.
+///
+/// Set with [`CacheBuilder::durability`](crate::CacheBuilder::durability). Stronger modes call
+/// `fsync` more often, which makes writes slower.
+///
+/// These modes only cover the entry files. The metadata database persists writes on its own
+/// schedule, see [`Cache::flush`](super::Cache::flush). The only exception is the journal of
+/// writes and removals in progress, which is flushed before any file is changed in the
+/// [`Data`](Self::Data) and [`DataAndDirectory`](Self::DataAndDirectory) modes, so an interrupted
+/// operation can always be completed or undone when the cache is built again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Durability {
+    /// Nothing is synced, the operating system decides when data reaches the disk. After a power
+    /// loss, entries may be empty or contain partial data.
+    #[default]
+    None,
+    /// The data of an entry is synced to disk before it replaces the previous value, so an entry
+    /// is never visible with partial data.
+    Data,
+    /// Like [`Data`](Self::Data), but the directory containing the entry is also synced after
+    /// the entry is moved into place or removed, so the change itself survives a power loss.
+    ///
+    /// Directories can only be synced on unix platforms. Elsewhere this is the same as
+    /// [`Data`](Self::Data).
+    DataAndDirectory,
+}
+
+impl Durability {
+    /// Syncs the data of a freshly written `file`, if this mode requires it
+    pub(super) async fn sync_file(self, file: &afs::File) -> Result<()> {
+        if self >= Self::Data {
+            file.sync_all().await.map_err(ForcepError::Io)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the parent directory of `path` after an entry was moved into or out of it, if this
+    /// mode requires it
+    pub(super) async fn sync_parent(self, path: &path::Path) -> Result<()> {
+        if self < Self::DataAndDirectory {
+            return Ok(());
+        }
+        match path.parent() {
+            Some(parent) => sync_dir(parent).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Syncs the directory entries of `dir` to disk
+#[cfg(unix)]
+async fn sync_dir(dir: &path::Path) -> Result<()> {
+    let dir = afs::File::open(dir).await.map_err(ForcepError::Io)?;
+    dir.sync_all().await.map_err(ForcepError::Io)
+}
+
+/// Directories can't be opened (and therefore synced) like files outside of unix
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &path::Path) -> Result<()> {
+    Ok(())
+}
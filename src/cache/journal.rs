@@ -0,0 +1,103 @@
+use super::{Durability, Options, quarantine, transaction};
+use crate::{ForcepError, Intent, MetaDb, Result};
+use std::io;
+use std::path;
+use tokio::fs as afs;
+
+/// The name of the temporary file at `path`, as recorded in an [`Intent`]. Fails if it isn't the
+/// name of a temporary file, since replaying the intent would then touch other files.
+pub(super) fn tmp_name(path: &path::Path) -> Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| crate::tmp::is_tmpname(name))
+        .map(str::to_owned)
+        .ok_or_else(|| {
+            ForcepError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a temporary file",
+            ))
+        })
+}
+
+/// Records `intent` in the journal of `meta`, returning its id. If the durability mode requires
+/// it, the journal is flushed to disk before any file is changed.
+pub(super) fn begin(meta: &MetaDb, opts: &Options, intent: &Intent) -> Result<u64> {
+    let id = meta.begin_intent(intent)?;
+    if opts.durability >= Durability::Data {
+        meta.flush()?;
+    }
+    Ok(id)
+}
+
+/// Removes the file at `path`, returning whether it existed
+pub(super) async fn remove_if_exists(path: &path::Path) -> Result<bool> {
+    match afs::remove_file(path).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(ForcepError::Io(e)),
+    }
+}
+
+/// Completes or undoes every operation in the journal of `meta` that was interrupted
+/// before both the file and the metadata of its entry were changed, returning how many there
+/// were.
+///
+/// Each operation ends up either fully applied or not applied at all, depending on whether its
+/// file was already changed. Intents that can't be read, or that don't name a temporary file, are
+/// dropped without touching any file.
+pub(super) async fn replay(meta: &MetaDb, opts: &Options) -> Result<u64> {
+    let intents = meta.intents()?;
+    for (id, intent) in &intents {
+        let intent = intent.as_ref().filter(|intent| match intent {
+            Intent::Write { tmp, .. } | Intent::Remove { tmp, .. } => crate::tmp::is_tmpname(tmp),
+            Intent::Quarantine { id, .. } => quarantine::is_id(id),
+            Intent::Transaction { changes } => changes.iter().all(|change| {
+                change.tmp.as_deref().is_none_or(crate::tmp::is_tmpname)
+                    && crate::tmp::is_tmpname(&change.backup)
+            }),
+        });
+        // malformed records are dropped
+        let Some(intent) = intent else {
+            meta.end_intent(*id)?;
+            continue;
+        };
+        match intent {
+            Intent::Write {
+                key,
+                tmp,
+                meta: written,
+            } => {
+                // if the staged file is still there, the entry was never changed and the write is
+                // simply undone. otherwise the metadata has to match whatever file is in place.
+                if !remove_if_exists(&opts.path.join(tmp)).await? {
+                    let data = match afs::read(opts.path_from_key(key)).await {
+                        Ok(data) => Some(data),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                        Err(e) => return Err(ForcepError::Io(e)),
+                    };
+                    if data.is_some_and(|data| written.check_integrity_of(&data)) {
+                        meta.complete_write(key, written.clone())?;
+                    }
+                }
+            }
+            Intent::Remove { key, tmp } => {
+                remove_if_exists(&opts.path.join(tmp)).await?;
+                // once the file is gone, the removal can only be completed
+                let exists = afs::try_exists(opts.path_from_key(key))
+                    .await
+                    .map_err(ForcepError::Io)?;
+                if !exists {
+                    match meta.remove_metadata_for(key) {
+                        Ok(_) | Err(ForcepError::MetaNotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Intent::Quarantine { key, id } => quarantine::replay(meta, opts, key, id).await?,
+            // the batch updating the metadata ends the intent, so it was never applied
+            Intent::Transaction { changes } => transaction::replay(opts, changes).await?,
+        }
+        meta.end_intent(*id)?;
+    }
+    Ok(intents.len() as u64)
+}
@@ -0,0 +1,265 @@
+use super::{Cache, WriteOptions};
+use crate::{Metadata, Result};
+use std::hash::{BuildHasher, RandomState};
+use tokio::sync::futures::Notified;
+use tokio::sync::{Mutex, MutexGuard, Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Number of stripes keys are spread over
+const STRIPES: usize = 256;
+
+#[derive(Debug, Default)]
+struct Stripe {
+    /// Held while an operation changes any of the entries of the stripe
+    lock: Mutex<()>,
+    /// The keys of the stripe that are locked by a [`KeyGuard`]
+    guarded: parking_lot::Mutex<Vec<Vec<u8>>>,
+    /// Notified whenever a [`KeyGuard`] of the stripe is dropped
+    released: Notify,
+    /// Held for reading while an entry of the stripe is read, and for writing while a
+    /// transaction changing any of them is published
+    readers: RwLock<()>,
+}
+
+impl Stripe {
+    /// Returns a future that completes once a guard of the stripe is dropped if `key` is locked
+    /// by a [`KeyGuard`], or `None` if it isn't.
+    fn guarded(&self, key: &[u8]) -> Option<Notified<'_>> {
+        let guarded = self.guarded.lock();
+        // created while the keys are locked, so a guard dropped after the check can't be missed
+        guarded
+            .iter()
+            .any(|k| k == key)
+            .then(|| self.released.notified())
+    }
+}
+
+/// Striped async locks that serialize the operations changing the same entry.
+///
+/// Every key maps to one of a fixed number of stripes, so unrelated keys may share a lock. That
+/// only costs some concurrency, since operations only hold a stripe while they run. Entries
+/// locked for longer by a [`KeyGuard`] are recorded by their exact key, so they only hold up
+/// operations on that key. Nothing ever waits for a [`KeyGuard`] while holding a stripe, which
+/// keeps the guards out of the lock order of the stripes.
+#[derive(Debug)]
+pub(super) struct KeyLocks {
+    stripes: Box<[Stripe]>,
+    hasher: RandomState,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self {
+            stripes: (0..STRIPES).map(|_| Stripe::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl KeyLocks {
+    /// The index of the stripe that `key` maps to
+    #[inline]
+    fn stripe(&self, key: &[u8]) -> usize {
+        (self.hasher.hash_one(key) % self.stripes.len() as u64) as usize
+    }
+
+    /// Locks the stripe of `key`, waiting until it's available and `key` isn't locked by a
+    /// [`KeyGuard`].
+    pub async fn lock(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        let stripe = &self.stripes[self.stripe(key)];
+        loop {
+            let guard = stripe.lock.lock().await;
+            let Some(released) = stripe.guarded(key) else {
+                return guard;
+            };
+            drop(guard);
+            released.await;
+        }
+    }
+
+    /// Locks the stripe of `key` like [`lock`](Self::lock), or returns `None` right away if
+    /// `key` is locked by a [`KeyGuard`].
+    pub async fn lock_unguarded(&self, key: &[u8]) -> Option<MutexGuard<'_, ()>> {
+        let stripe = &self.stripes[self.stripe(key)];
+        let guard = stripe.lock.lock().await;
+        stripe.guarded(key).is_none().then_some(guard)
+    }
+
+    /// Locks the stripes of every key in `keys` at once.
+    ///
+    /// The stripes are always locked in the same order, so this can't deadlock with another call
+    /// locking some of the same stripes. If any of the keys is locked by a [`KeyGuard`], every
+    /// stripe is released again until the guard is dropped.
+    pub async fn lock_all<'k, I>(&self, keys: I) -> Vec<MutexGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = &'k [u8]>,
+    {
+        let mut keys = keys
+            .into_iter()
+            .map(|k| (self.stripe(k), k))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+
+        'retry: loop {
+            let mut guards = Vec::with_capacity(keys.len());
+            let mut locked = None;
+            for &(index, key) in &keys {
+                let stripe = &self.stripes[index];
+                if locked != Some(index) {
+                    guards.push(stripe.lock.lock().await);
+                    locked = Some(index);
+                }
+                if let Some(released) = stripe.guarded(key) {
+                    drop(guards);
+                    released.await;
+                    continue 'retry;
+                }
+            }
+            return guards;
+        }
+    }
+
+    /// Waits until no transaction is publishing a change to the stripe of `key`, and keeps any
+    /// from starting until the returned guard is dropped.
+    pub async fn read(&self, key: &[u8]) -> RwLockReadGuard<'_, ()> {
+        self.stripes[self.stripe(key)].readers.read().await
+    }
+
+    /// Waits for the readers of the stripes of every key in `keys` to finish, and keeps new ones
+    /// waiting until the returned guards are dropped.
+    ///
+    /// The stripes have to be locked with [`lock_all`](Self::lock_all) first, which keeps this
+    /// in the same order as the stripes themselves.
+    pub async fn exclude_readers<'k, I>(&self, keys: I) -> Vec<RwLockWriteGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = &'k [u8]>,
+    {
+        let mut stripes = keys.into_iter().map(|k| self.stripe(k)).collect::<Vec<_>>();
+        stripes.sort_unstable();
+        stripes.dedup();
+
+        let mut guards = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            guards.push(self.stripes[stripe].readers.write().await);
+        }
+        guards
+    }
+
+    /// Locks `key` on behalf of a [`KeyGuard`], waiting for any operation on the stripe of `key`
+    /// to finish and for any other guard of `key` to be dropped. Returns the index of the
+    /// stripe, which has to be passed to [`release`](Self::release) once the guard is dropped.
+    async fn acquire(&self, key: &[u8]) -> usize {
+        let index = self.stripe(key);
+        let stripe = &self.stripes[index];
+        loop {
+            let guard = stripe.lock.lock().await;
+            let released = {
+                let mut guarded = stripe.guarded.lock();
+                if !guarded.iter().any(|k| k == key) {
+                    guarded.push(key.to_vec());
+                    return index;
+                }
+                stripe.released.notified()
+            };
+            drop(guard);
+            released.await;
+        }
+    }
+
+    /// Unlocks `key`, which was locked by [`acquire`](Self::acquire) in the stripe `index`.
+    fn release(&self, index: usize, key: &[u8]) {
+        let stripe = &self.stripes[index];
+        {
+            let mut guarded = stripe.guarded.lock();
+            if let Some(pos) = guarded.iter().position(|k| k == key) {
+                guarded.swap_remove(pos);
+            }
+        }
+        stripe.released.notify_waiters();
+    }
+}
+
+/// Exclusive access to a single entry of a [`Cache`], acquired with [`Cache::lock_key`].
+///
+/// While the guard is held, every other write or removal of the entry (including transactions)
+/// waits until it's dropped, so a read followed by a write through the guard can't lose an update
+/// made in between. Evictions skip the entry instead, and reads are never blocked.
+///
+/// The guard only locks its own key, so other entries can be changed as usual while it's held.
+/// The locked entry itself has to be changed through the guard: calling [`Cache::write`],
+/// [`Cache::remove`] or [`Cache::lock_key`] for the same key, or committing a
+/// [`Transaction`](crate::Transaction) that includes it, waits until the guard is dropped, and so
+/// never finishes in the task holding it. The guard can be moved to (and used from) any task.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::Cache;
+///
+/// let cache = Cache::new("./cache")
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let guard = cache.lock_key(b"COUNTER").await;
+/// let count = match cache.read(b"COUNTER").await {
+///     Ok(data) => u64::from_le_bytes(data.as_ref().try_into().unwrap()),
+///     Err(_) => 0,
+/// };
+/// guard.write((count + 1).to_le_bytes()).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+#[must_use = "the entry is unlocked as soon as the guard is dropped"]
+pub struct KeyGuard<'a> {
+    cache: &'a Cache,
+    key: Vec<u8>,
+    stripe: usize,
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        self.cache.locks.release(self.stripe, &self.key);
+    }
+}
+
+impl<'a> KeyGuard<'a> {
+    /// Locks the entry `key` of `cache`, waiting for any other write, removal or guard of it.
+    pub(super) async fn lock(cache: &'a Cache, key: Vec<u8>) -> Self {
+        let stripe = cache.locks.acquire(&key).await;
+        Self { cache, key, stripe }
+    }
+
+    /// The key of the locked entry
+    #[inline]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Writes `value` to the locked entry, like [`Cache::write`].
+    #[inline]
+    pub async fn write<V: AsRef<[u8]>>(&self, value: V) -> Result<Metadata> {
+        self.write_with(value, WriteOptions::default()).await
+    }
+
+    /// Writes `value` to the locked entry using the provided [`WriteOptions`], like
+    /// [`Cache::write_with`].
+    #[inline]
+    pub async fn write_with<V: AsRef<[u8]>>(
+        &self,
+        value: V,
+        opts: WriteOptions,
+    ) -> Result<Metadata> {
+        self.cache
+            .write_locked(&self.key, value.as_ref(), opts)
+            .await
+    }
+
+    /// Removes the locked entry, like [`Cache::remove`].
+    #[inline]
+    pub async fn remove(&self) -> Result<Metadata> {
+        self.cache.remove_locked(&self.key).await
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{ForcepError, Result};
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::time::{Duration, Instant};
+
+/// Name of the lock file in the cache directory
+const LOCK_FILE: &str = "forceps.lock";
+
+/// How often the lock is retried while waiting for it
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How [`CacheBuilder::build`](crate::CacheBuilder::build) acquires the lock on the cache
+/// directory.
+///
+/// Only one [`Cache`](super::Cache) can use a cache directory at a time, which is enforced with a
+/// lock file (`forceps.lock`) in the directory. The lock is held until the cache is dropped, and
+/// is released by the operating system if the process exits. Set with
+/// [`CacheBuilder::lock_mode`](crate::CacheBuilder::lock_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LockMode {
+    /// Fails immediately with [`ForcepError::Locked`] if the directory is locked by another
+    /// cache.
+    #[default]
+    Exclusive,
+    /// Waits up to the given duration for the directory to be unlocked, and fails with
+    /// [`ForcepError::Locked`] if it's still locked by then.
+    Wait(Duration),
+}
+
+/// An exclusive lock on a cache directory, released when dropped
+#[derive(Debug)]
+pub(super) struct DirLock {
+    _file: fs::File,
+}
+
+impl DirLock {
+    /// Acquires the lock on the cache directory `dir` according to `mode`, writing the PID of
+    /// this process to the lock file so other processes can tell who holds it.
+    pub async fn acquire(dir: &path::Path, mode: LockMode) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(ForcepError::Io)?;
+
+        let deadline = match mode {
+            LockMode::Exclusive => None,
+            LockMode::Wait(timeout) => Some(Instant::now() + timeout),
+        };
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(fs::TryLockError::WouldBlock) => {}
+                Err(fs::TryLockError::Error(e)) => return Err(ForcepError::Io(e)),
+            }
+            match deadline {
+                Some(deadline) if Instant::now() < deadline => {
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+                _ => {
+                    return Err(ForcepError::Locked {
+                        pid: holder_pid(&path),
+                    });
+                }
+            }
+        }
+
+        file.set_len(0).map_err(ForcepError::Io)?;
+        write!(file, "{}", std::process::id()).map_err(ForcepError::Io)?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Reads the PID of the process holding the lock from the lock file at `path`, if possible
+fn holder_pid(path: &path::Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,317 @@
+use super::{Cache, Options, WriteOptions, access_buffer::AccessBuffer, journal, scrub};
+use crate::{ForcepError, Intent, MetaDb, Metadata, Result, mem_cache::MemCache};
+use std::io;
+use std::path;
+use std::time::{Duration, SystemTime};
+use tokio::fs as afs;
+
+/// Name of the quarantine directory in the cache directory
+const QUARANTINE_DIR: &str = "quarantine";
+/// Extension of the record files next to the quarantined data
+const RECORD_EXT: &str = "meta";
+/// Number of hex characters in the id of a quarantined entry
+const ID_LEN: usize = 16;
+
+/// An entry that failed verification and was moved to the quarantine area of the cache, instead
+/// of being deleted.
+///
+/// Quarantined entries can't be read from the cache anymore, but their data is kept (at
+/// [`path`](Self::path)) for inspection. They're listed with [`Cache::quarantined`], and can be
+/// restored with [`Cache::restore_quarantined`] or deleted with [`Cache::purge_quarantined`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct QuarantinedEntry {
+    /// The id of the quarantined entry, which identifies it in the quarantine area
+    pub id: String,
+    /// The key the entry was stored under
+    pub key: Vec<u8>,
+    /// The metadata of the entry at the time it was quarantined
+    pub metadata: Metadata,
+    /// The integrity hash of the quarantined data, computed with the algorithm of the metadata.
+    /// Empty if the algorithm isn't available.
+    pub detected_integrity: Vec<u8>,
+    /// When the entry was quarantined
+    pub quarantined_at: SystemTime,
+    /// The path of the quarantined data
+    pub path: path::PathBuf,
+}
+
+/// Creates a new random id for a quarantined entry
+fn new_id() -> String {
+    format!("{:0width$x}", rand::random::<u64>(), width = ID_LEN)
+}
+
+/// Whether `id` could have been created by [`new_id`], so user supplied ids can't point outside
+/// of the quarantine directory
+pub(super) fn is_id(id: &str) -> bool {
+    id.len() == ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The quarantine directory of the cache described by `opts`
+fn dir(opts: &Options) -> path::PathBuf {
+    opts.path.join(QUARANTINE_DIR)
+}
+
+/// The path of the record of the quarantined entry `id`
+fn record_path(opts: &Options, id: &str) -> path::PathBuf {
+    dir(opts).join(format!("{id}.{RECORD_EXT}"))
+}
+
+/// Serializes the record of a quarantined entry into bytes
+fn serialize_record(
+    key: &[u8],
+    meta: &Metadata,
+    detected: &[u8],
+    at: SystemTime,
+) -> Result<Vec<u8>> {
+    use bson::{
+        cstr,
+        raw::{RawBinaryRef, RawDocumentBuf},
+        spec::BinarySubtype,
+    };
+    let binary = |bytes| RawBinaryRef {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    };
+
+    let millis = at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let meta = meta.serialize()?;
+    let mut doc = RawDocumentBuf::new();
+    doc.append(cstr!("key"), binary(key));
+    doc.append(cstr!("meta"), binary(&meta));
+    doc.append(cstr!("detected"), binary(detected));
+    doc.append(cstr!("at"), millis);
+    Ok(doc.into_bytes())
+}
+
+/// Deserializes the record of the quarantined entry `id`
+fn deserialize_record(opts: &Options, id: &str, buf: &[u8]) -> Result<QuarantinedEntry> {
+    use bson::raw::RawDocument;
+
+    let doc = RawDocument::from_bytes(buf).map_err(ForcepError::MetaDe)?;
+    let binary = |name| {
+        doc.get_binary(name)
+            .map(|b| b.bytes.to_vec())
+            .map_err(ForcepError::MetaDe)
+    };
+    let millis = doc.get_i64("at").map_err(ForcepError::MetaDe)?;
+    Ok(QuarantinedEntry {
+        id: id.to_owned(),
+        key: binary("key")?,
+        metadata: Metadata::deserialize(&binary("meta")?)?,
+        detected_integrity: binary("detected")?,
+        quarantined_at: SystemTime::UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64),
+        path: dir(opts).join(id),
+    })
+}
+
+/// Moves the entry `key` described by `expected` to the quarantine area and removes its
+/// metadata, returning the id it was quarantined under, or `None` if its file doesn't exist.
+///
+/// The record is written before the data is moved and the move is journaled, so an interrupted
+/// move never loses the data: it's either completed or undone when the cache is built again.
+///
+/// The caller must hold the lock of `key`.
+pub(super) async fn move_in(
+    meta: &MetaDb,
+    mem: &MemCache,
+    access: Option<&AccessBuffer>,
+    opts: &Options,
+    key: &[u8],
+    expected: &Metadata,
+) -> Result<Option<String>> {
+    let src = opts.path_from_key(key);
+    let Some((_, detected)) = scrub::digest_file(&src, expected.get_integrity_algorithm()).await?
+    else {
+        return Ok(None);
+    };
+
+    afs::create_dir_all(dir(opts))
+        .await
+        .map_err(ForcepError::Io)?;
+    let id = new_id();
+    let record = serialize_record(
+        key,
+        expected,
+        &detected.unwrap_or_default(),
+        SystemTime::now(),
+    )?;
+    let record_path = record_path(opts, &id);
+    afs::write(&record_path, record)
+        .await
+        .map_err(ForcepError::Io)?;
+    opts.durability.sync_parent(&record_path).await?;
+
+    let intent = journal::begin(
+        meta,
+        opts,
+        &Intent::Quarantine {
+            key: key.to_vec(),
+            id: id.clone(),
+        },
+    )?;
+    let dest = dir(opts).join(&id);
+    if let Err(e) = afs::rename(&src, &dest).await {
+        // nothing was moved, so the record has nothing to describe
+        journal::remove_if_exists(&record_path).await?;
+        meta.end_intent(intent)?;
+        return match e.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(ForcepError::Io(e)),
+        };
+    }
+    mem.remove(key);
+    opts.durability.sync_parent(&src).await?;
+    opts.durability.sync_parent(&dest).await?;
+    // the entry may have been purged while its data was moved, which has to delete the data too
+    if !afs::try_exists(&record_path)
+        .await
+        .map_err(ForcepError::Io)?
+    {
+        journal::remove_if_exists(&dest).await?;
+    }
+
+    match meta.remove_metadata_for(key) {
+        Ok(_) | Err(ForcepError::MetaNotFound) => meta.end_intent(intent)?,
+        Err(e) => return Err(e),
+    }
+    if let Some(access) = access {
+        access.discard(key);
+    }
+    Ok(Some(id))
+}
+
+/// Completes or undoes the interrupted move of the entry `key` to the quarantined entry `id`, see
+/// [`move_in`]. The move is completed if the data was already moved, otherwise its record is
+/// removed again.
+pub(super) async fn replay(meta: &MetaDb, opts: &Options, key: &[u8], id: &str) -> Result<()> {
+    let moved = afs::try_exists(dir(opts).join(id))
+        .await
+        .map_err(ForcepError::Io)?;
+    if !moved {
+        journal::remove_if_exists(&record_path(opts, id)).await?;
+        return Ok(());
+    }
+    match meta.remove_metadata_for(key) {
+        Ok(_) | Err(ForcepError::MetaNotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the quarantined entry `id`, failing with [`ForcepError::NotFound`] if it doesn't exist
+async fn get(opts: &Options, id: &str) -> Result<QuarantinedEntry> {
+    if !is_id(id) {
+        return Err(ForcepError::NotFound);
+    }
+    let record = afs::read(record_path(opts, id))
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ForcepError::NotFound,
+            _ => ForcepError::Io(e),
+        })?;
+    deserialize_record(opts, id, &record)
+}
+
+/// The ids of every record in the quarantine area of the cache described by `opts`, including
+/// records whose data is missing
+async fn ids(opts: &Options) -> Result<Vec<String>> {
+    let mut entries = match afs::read_dir(dir(opts)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ForcepError::Io(e)),
+    };
+    let mut ids = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(ForcepError::Io)? {
+        let name = entry.file_name();
+        let id = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(RECORD_EXT)?.strip_suffix('.'))
+            .filter(|id| is_id(id));
+        ids.extend(id.map(str::to_owned));
+    }
+    Ok(ids)
+}
+
+/// Lists every entry in the quarantine area of the cache described by `opts`, oldest first.
+///
+/// Records whose data is missing are skipped: the data is either still being moved in, or was
+/// deleted by hand (in which case [`purge`] removes the record).
+pub(super) async fn list(opts: &Options) -> Result<Vec<QuarantinedEntry>> {
+    let mut quarantined = Vec::new();
+    for id in ids(opts).await? {
+        let exists = afs::try_exists(dir(opts).join(&id))
+            .await
+            .map_err(ForcepError::Io)?;
+        if !exists {
+            continue;
+        }
+        match get(opts, &id).await {
+            Ok(entry) => quarantined.push(entry),
+            // purged in the meantime
+            Err(ForcepError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    quarantined.sort_by_key(|entry| entry.quarantined_at);
+    Ok(quarantined)
+}
+
+/// Writes the data of the quarantined entry `id` back to its key, see
+/// [`Cache::restore_quarantined`].
+pub(super) async fn restore(cache: &Cache, id: &str) -> Result<Metadata> {
+    cache.ensure_writable()?;
+    let entry = get(&cache.opts, id).await?;
+    let data = afs::read(&entry.path).await.map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ForcepError::NotFound,
+        _ => ForcepError::Io(e),
+    })?;
+
+    let opts = WriteOptions {
+        attributes: entry.metadata.get_attributes().clone(),
+        tags: entry.metadata.get_tags().iter().cloned().collect(),
+        if_version: None,
+    };
+    let meta = cache.write_with(&entry.key, data, opts).await?;
+    purge(&cache.opts, id).await?;
+    Ok(meta)
+}
+
+/// Deletes the quarantined entry `id`, see [`Cache::purge_quarantined`].
+pub(super) async fn purge(opts: &Options, id: &str) -> Result<()> {
+    if !is_id(id) {
+        return Err(ForcepError::NotFound);
+    }
+    // the record goes first, so the data can't be left behind without one
+    afs::remove_file(record_path(opts, id))
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ForcepError::NotFound,
+            _ => ForcepError::Io(e),
+        })?;
+    match afs::remove_file(dir(opts).join(id)).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ForcepError::Io(e)),
+    }
+}
+
+/// Deletes every quarantined entry, returning how many there were. Records whose data is
+/// missing are deleted as well, but not counted. See [`Cache::purge_quarantine`].
+pub(super) async fn purge_all(opts: &Options) -> Result<u64> {
+    let mut purged = 0;
+    for id in ids(opts).await? {
+        let exists = afs::try_exists(dir(opts).join(&id))
+            .await
+            .map_err(ForcepError::Io)?;
+        match purge(opts, &id).await {
+            Ok(()) => purged += exists as u64,
+            // purged by someone else in the meantime
+            Err(ForcepError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(purged)
+}
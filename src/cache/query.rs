@@ -0,0 +1,360 @@
+use super::Cache;
+use crate::{AttributeValue, Metadata, Result, TimeIndex};
+use std::cmp;
+use std::fmt;
+use std::time;
+
+/// The [`Metadata`] field that the results of a [`Query`] are sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuerySort {
+    /// Sort by [`Metadata::get_size`]
+    Size,
+    /// Sort by [`Metadata::get_last_accessed_raw`]
+    LastAccessed,
+    /// Sort by [`Metadata::get_last_modified_raw`]
+    LastModified,
+    /// Sort by [`Metadata::get_created_at_raw`]
+    CreatedAt,
+    /// Sort by [`Metadata::get_hits`]
+    Hits,
+}
+
+impl QuerySort {
+    /// The time index that is ordered by this field, if there is one
+    fn index(self) -> Option<TimeIndex> {
+        match self {
+            Self::LastAccessed => Some(TimeIndex::LastAccessed),
+            Self::LastModified => Some(TimeIndex::LastModified),
+            _ => None,
+        }
+    }
+
+    /// Compares two entries by this field, in ascending order
+    fn compare(self, a: &Metadata, b: &Metadata) -> cmp::Ordering {
+        match self {
+            Self::Size => a.get_size().cmp(&b.get_size()),
+            Self::LastAccessed => a.get_last_accessed_raw().cmp(&b.get_last_accessed_raw()),
+            Self::LastModified => a.get_last_modified_raw().cmp(&b.get_last_modified_raw()),
+            Self::CreatedAt => a.get_created_at_raw().cmp(&b.get_created_at_raw()),
+            Self::Hits => a.get_hits().cmp(&b.get_hits()),
+        }
+    }
+}
+
+/// A single condition that an entry must meet to be part of the results of a [`Query`]
+enum Predicate {
+    MinSize(u64),
+    MaxSize(u64),
+    AccessedBefore(u64),
+    AccessedAfter(u64),
+    ModifiedBefore(u64),
+    ModifiedAfter(u64),
+    MinHits(u64),
+    MaxHits(u64),
+    HasAttribute(String),
+    AttributeEq(String, AttributeValue),
+    Custom(Box<dyn Fn(&Metadata) -> bool + Send + Sync>),
+}
+
+impl Predicate {
+    fn matches(&self, meta: &Metadata) -> bool {
+        match self {
+            Self::MinSize(v) => meta.get_size() >= *v,
+            Self::MaxSize(v) => meta.get_size() <= *v,
+            Self::AccessedBefore(v) => meta.get_last_accessed_raw() < *v,
+            Self::AccessedAfter(v) => meta.get_last_accessed_raw() >= *v,
+            Self::ModifiedBefore(v) => meta.get_last_modified_raw() < *v,
+            Self::ModifiedAfter(v) => meta.get_last_modified_raw() >= *v,
+            Self::MinHits(v) => meta.get_hits() >= *v,
+            Self::MaxHits(v) => meta.get_hits() <= *v,
+            Self::HasAttribute(name) => meta.get_attribute(name).is_some(),
+            Self::AttributeEq(name, value) => meta.get_attribute(name) == Some(value),
+            Self::Custom(f) => f(meta),
+        }
+    }
+
+    /// The upper bound on the timestamps of `index` that this predicate sets, if any
+    fn time_bound(&self, index: TimeIndex) -> Option<u64> {
+        match (self, index) {
+            (Self::AccessedBefore(v), TimeIndex::LastAccessed) => Some(*v),
+            (Self::ModifiedBefore(v), TimeIndex::LastModified) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinSize(v) => f.debug_tuple("MinSize").field(v).finish(),
+            Self::MaxSize(v) => f.debug_tuple("MaxSize").field(v).finish(),
+            Self::AccessedBefore(v) => f.debug_tuple("AccessedBefore").field(v).finish(),
+            Self::AccessedAfter(v) => f.debug_tuple("AccessedAfter").field(v).finish(),
+            Self::ModifiedBefore(v) => f.debug_tuple("ModifiedBefore").field(v).finish(),
+            Self::ModifiedAfter(v) => f.debug_tuple("ModifiedAfter").field(v).finish(),
+            Self::MinHits(v) => f.debug_tuple("MinHits").field(v).finish(),
+            Self::MaxHits(v) => f.debug_tuple("MaxHits").field(v).finish(),
+            Self::HasAttribute(n) => f.debug_tuple("HasAttribute").field(n).finish(),
+            Self::AttributeEq(n, v) => f.debug_tuple("AttributeEq").field(n).field(v).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Milliseconds from epoch to `t`
+fn millis_of(t: time::SystemTime) -> u64 {
+    t.duration_since(time::UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A builder for querying the entries of a [`Cache`] by their [`Metadata`].
+///
+/// Created with [`Cache::query`]. Every condition added to the query must be met for an entry to
+/// be part of the results. The results can optionally be sorted and limited.
+///
+/// # Indexes
+///
+/// Instead of scanning every entry, queries use the secondary indexes of the metadata database
+/// when possible:
+///
+/// * Queries with a [`tag`](Self::tag) only look at the entries with that tag.
+/// * Queries with an [`accessed_before`](Self::accessed_before) or
+///   [`modified_before`](Self::modified_before) condition only walk the matching part of the time
+///   index.
+/// * Queries sorted by access or modification time in ascending order read the time index in
+///   order, and stop as soon as the [`limit`](Self::limit) is reached.
+///
+/// Accesses buffered by [`CacheBuilder::buffer_access_tracking`](crate::CacheBuilder::buffer_access_tracking)
+/// are included in the returned metadata, but the access time index only reflects them once they
+/// are flushed.
+///
+/// # Non-Async
+///
+/// Like [`Cache::metadata_iter`], queries only read metadata, so executing them is not async.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::{Cache, QuerySort};
+/// use std::time::{Duration, SystemTime};
+///
+/// let cache = Cache::new("./cache")
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+/// let stale = cache
+///     .query()
+///     .min_size(1024 * 1024)
+///     .accessed_before(an_hour_ago)
+///     .sort_by(QuerySort::LastAccessed)
+///     .limit(10)
+///     .execute()
+///     .unwrap();
+/// for (key, meta) in stale {
+///     println!("{} is {} bytes", String::from_utf8_lossy(&key), meta.get_size());
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+#[must_use = "queries do nothing until they are executed"]
+pub struct Query<'a> {
+    cache: &'a Cache,
+    predicates: Vec<Predicate>,
+    tag: Option<String>,
+    sort: Option<(QuerySort, bool)>,
+    limit: Option<usize>,
+}
+
+impl<'a> Query<'a> {
+    pub(super) fn new(cache: &'a Cache) -> Self {
+        Self {
+            cache,
+            predicates: Vec::new(),
+            tag: None,
+            sort: None,
+            limit: None,
+        }
+    }
+
+    #[inline]
+    fn with(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    /// Only matches entries that are at least `size` bytes.
+    pub fn min_size(self, size: u64) -> Self {
+        self.with(Predicate::MinSize(size))
+    }
+    /// Only matches entries that are at most `size` bytes.
+    pub fn max_size(self, size: u64) -> Self {
+        self.with(Predicate::MaxSize(size))
+    }
+
+    /// Only matches entries that were last accessed before `t`.
+    pub fn accessed_before(self, t: time::SystemTime) -> Self {
+        self.with(Predicate::AccessedBefore(millis_of(t)))
+    }
+    /// Only matches entries that were last accessed at or after `t`.
+    pub fn accessed_after(self, t: time::SystemTime) -> Self {
+        self.with(Predicate::AccessedAfter(millis_of(t)))
+    }
+
+    /// Only matches entries that were last modified before `t`.
+    pub fn modified_before(self, t: time::SystemTime) -> Self {
+        self.with(Predicate::ModifiedBefore(millis_of(t)))
+    }
+    /// Only matches entries that were last modified at or after `t`.
+    pub fn modified_after(self, t: time::SystemTime) -> Self {
+        self.with(Predicate::ModifiedAfter(millis_of(t)))
+    }
+
+    /// Only matches entries that have been read at least `hits` times.
+    pub fn min_hits(self, hits: u64) -> Self {
+        self.with(Predicate::MinHits(hits))
+    }
+    /// Only matches entries that have been read at most `hits` times.
+    pub fn max_hits(self, hits: u64) -> Self {
+        self.with(Predicate::MaxHits(hits))
+    }
+
+    /// Only matches entries that have the user-defined attribute `name`, regardless of its value.
+    pub fn has_attribute<N: Into<String>>(self, name: N) -> Self {
+        self.with(Predicate::HasAttribute(name.into()))
+    }
+    /// Only matches entries whose user-defined attribute `name` is equal to `value`.
+    pub fn attribute_eq<N, V>(self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<AttributeValue>,
+    {
+        self.with(Predicate::AttributeEq(name.into(), value.into()))
+    }
+
+    /// Only matches entries tagged with `tag`.
+    ///
+    /// Only a single tag can be used per query; setting it again replaces the previous tag.
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Only matches entries for which `f` returns `true`.
+    pub fn filter<F>(self, f: F) -> Self
+    where
+        F: Fn(&Metadata) -> bool + Send + Sync + 'static,
+    {
+        self.with(Predicate::Custom(Box::new(f)))
+    }
+
+    /// Sorts the results by `field`, in ascending order.
+    pub fn sort_by(mut self, field: QuerySort) -> Self {
+        self.sort = Some((field, false));
+        self
+    }
+    /// Sorts the results by `field`, in descending order.
+    pub fn sort_by_desc(mut self, field: QuerySort) -> Self {
+        self.sort = Some((field, true));
+        self
+    }
+
+    /// Returns at most `n` results. When the results are sorted, these are the first `n` results
+    /// after sorting.
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    /// Whether `meta` meets every condition of the query
+    fn matches(&self, meta: &Metadata) -> bool {
+        self.predicates.iter().all(|p| p.matches(meta))
+    }
+
+    /// Picks the time index to walk instead of the whole database, along with the bound to stop
+    /// walking at and whether the index order is already the requested sort order
+    fn time_source(&self) -> Option<(TimeIndex, Option<u64>, bool)> {
+        let sorted_index = match self.sort {
+            None => None,
+            Some((field, false)) => field.index(),
+            // the index can't be walked in reverse
+            Some((_, true)) => return None,
+        };
+        let bound_of = |index| {
+            self.predicates
+                .iter()
+                .filter_map(|p| p.time_bound(index))
+                .min()
+        };
+
+        if let Some(index) = sorted_index {
+            return Some((index, bound_of(index), true));
+        }
+        [TimeIndex::LastAccessed, TimeIndex::LastModified]
+            .into_iter()
+            .find_map(|index| {
+                bound_of(index).map(|bound| (index, Some(bound), self.sort.is_none()))
+            })
+    }
+
+    /// Runs the query, returning the key and metadata of every matching entry.
+    pub fn execute(self) -> Result<Vec<(Vec<u8>, Metadata)>> {
+        let meta_db = &self.cache.meta;
+        // fetches the metadata of a key from one of the indexes, skipping entries that were
+        // removed since the index was read
+        let fetch = |key: Vec<u8>| -> Result<Option<(Vec<u8>, Metadata)>> {
+            Ok(meta_db.get_metadata_opt(&key)?.map(|m| (key, m)))
+        };
+
+        let (source, ordered): (Box<dyn Iterator<Item = Result<_>>>, bool) =
+            if let Some(tag) = &self.tag {
+                let iter = meta_db
+                    .keys_with_tag(tag)
+                    .filter_map(move |x| x.and_then(fetch).transpose());
+                (Box::new(iter), self.sort.is_none())
+            } else if let Some((index, bound, ordered)) = self.time_source() {
+                // buffered accesses aren't in the index yet, so it may be out of order until they
+                // are merged below
+                let buffered = index == TimeIndex::LastAccessed && self.cache.access.is_some();
+                let iter = meta_db
+                    .keys_by_time(index)
+                    .take_while(move |x| match (x, bound) {
+                        (Ok((ts, _)), Some(bound)) => *ts < bound,
+                        _ => true,
+                    })
+                    .filter_map(move |x| x.and_then(|(_, key)| fetch(key)).transpose());
+                (Box::new(iter), ordered && !buffered)
+            } else {
+                (Box::new(meta_db.metadata_iter()), self.sort.is_none())
+            };
+
+        let mut results = Vec::new();
+        for result in source {
+            let (key, mut meta) = result?;
+            self.cache.merge_pending_access(&key, &mut meta);
+            if !self.matches(&meta) {
+                continue;
+            }
+            results.push((key, meta));
+            // once the results are in their final order, there's no need to look any further
+            if ordered && self.limit.is_some_and(|n| results.len() >= n) {
+                break;
+            }
+        }
+
+        if let Some((field, desc)) = self.sort {
+            results.sort_by(|(_, a), (_, b)| match desc {
+                false => field.compare(a, b),
+                true => field.compare(b, a),
+            });
+        }
+        if let Some(n) = self.limit {
+            results.truncate(n);
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,80 @@
+use super::{Cache, check, scrub};
+use crate::{Attributes, ForcepError, Metadata, Result};
+use std::io;
+use std::path;
+use std::time;
+
+/// Whether `e` (returned when opening the metadata database) means the database is corrupted,
+/// rather than temporarily unavailable or unsupported.
+pub(super) fn is_corruption(e: &ForcepError) -> bool {
+    match e {
+        ForcepError::MetaDb(sled::Error::Corruption { .. }) => true,
+        ForcepError::MetaDb(sled::Error::Io(e)) => is_corrupt_data(e),
+        ForcepError::MetaDe(_) | ForcepError::MetaVersion(None) => true,
+        // other backends can fail for any reason, so only their known corruption errors count
+        #[cfg(feature = "redb")]
+        ForcepError::Backend(e) => match e.downcast_ref::<redb::Error>() {
+            Some(redb::Error::Corrupted(_)) => true,
+            Some(redb::Error::Io(e)) => is_corrupt_data(e),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether the I/O error `e` means the data that was read is malformed. Errors like lock
+/// contention or missing permissions never count.
+fn is_corrupt_data(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Moves the corrupted metadata database at `storage` out of the way, so a new one can be
+/// created in its place. Returns the path it was moved to.
+pub(super) async fn move_aside(storage: &path::Path) -> Result<path::PathBuf> {
+    let millis = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let mut name = storage.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".corrupt-{millis}"));
+    let aside = storage.with_file_name(name);
+    tokio::fs::rename(storage, &aside)
+        .await
+        .map_err(ForcepError::Io)?;
+    Ok(aside)
+}
+
+/// Restores the metadata of every entry file in `cache` that has none, returning the number of
+/// entries that were restored. See [`Cache::rebuild_index`].
+pub(super) async fn run(cache: &Cache) -> Result<u64> {
+    let mut restored = 0;
+    for (key, path) in check::entry_files(cache).await? {
+        // the entry can't be written or removed between the check below and restoring it
+        let _guard = cache.locks.lock(&key).await;
+        if cache.meta.get_metadata_opt(&key)?.is_some() {
+            continue;
+        }
+        let alg = cache.opts.hash_alg;
+        // removed in the meantime
+        let Some((size, digest)) = scrub::digest_file(&path, alg).await? else {
+            continue;
+        };
+        // the file modification time is the best guess for when the entry was written
+        let modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_millis() as u64);
+
+        let integrity = digest.unwrap_or_default();
+        let meta = Metadata::with_digest(size, integrity, alg, Attributes::new(), Vec::new())
+            .with_times(modified);
+        if cache.meta.restore_metadata(&key, &meta)? {
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
@@ -0,0 +1,368 @@
+use super::{Options, access_buffer::AccessBuffer, key_lock::KeyLocks, quarantine};
+use crate::{ForcepError, HashAlgorithm, MetaDb, Result, mem_cache::MemCache};
+use parking_lot::Mutex;
+use std::io;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs as afs;
+use tokio::io::AsyncReadExt;
+
+/// Size of the chunks entries are hashed in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What the background scrubber does with entries that fail verification, see
+/// [`ScrubOptions::action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ScrubAction {
+    /// Corrupted entries are only reported in the [`ScrubStatus`]
+    #[default]
+    Report,
+    /// Corrupted entries are removed from the cache, and reported in the [`ScrubStatus`]
+    Remove,
+    /// Corrupted entries are moved to the quarantine area of the cache (see
+    /// [`Cache::quarantined`](crate::Cache::quarantined)), and reported in the [`ScrubStatus`]
+    Quarantine,
+}
+
+/// Options for the background integrity scrubber, enabled with
+/// [`CacheBuilder::scrub`](crate::CacheBuilder::scrub).
+///
+/// The scrubber slowly walks every entry of the cache, reading its data and verifying it against
+/// the integrity hash in its metadata. This finds entries that were silently corrupted on disk,
+/// before they are read.
+///
+/// # Examples
+///
+/// ```rust
+/// use forceps::{ScrubAction, ScrubOptions};
+/// use std::time::Duration;
+///
+/// let opts = ScrubOptions::new()
+///     .interval(Duration::from_secs(60 * 60))
+///     .max_bytes_per_sec(Some(1024 * 1024))
+///     .action(ScrubAction::Remove);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScrubOptions {
+    interval: Duration,
+    max_bytes_per_sec: Option<u64>,
+    action: ScrubAction,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(24 * 60 * 60),
+            max_bytes_per_sec: Some(8 * 1024 * 1024),
+            action: ScrubAction::Report,
+        }
+    }
+}
+
+impl ScrubOptions {
+    /// Creates a new [`ScrubOptions`] with the default settings.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the pause between two passes over the cache.
+    ///
+    /// **Default is `24 hours`**
+    ///
+    /// The first pass starts as soon as the cache is built.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the maximum number of bytes per second the scrubber reads from disk, or `None` to
+    /// read as fast as possible.
+    ///
+    /// **Default is `Some(8 MiB)`**
+    ///
+    /// This is an average over the whole pass, so the scrubber doesn't compete with regular
+    /// reads and writes for disk bandwidth.
+    pub fn max_bytes_per_sec(mut self, limit: Option<u64>) -> Self {
+        self.max_bytes_per_sec = limit;
+        self
+    }
+
+    /// Sets what happens to entries that fail verification.
+    ///
+    /// **Default is [`ScrubAction::Report`]**
+    ///
+    /// Read-only caches (see [`CacheBuilder::read_only`](crate::CacheBuilder::read_only)) only
+    /// ever report corrupted entries.
+    pub fn action(mut self, action: ScrubAction) -> Self {
+        self.action = action;
+        self
+    }
+}
+
+/// The progress and results of the background integrity scrubber, see
+/// [`Cache::scrub_status`](crate::Cache::scrub_status).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ScrubStatus {
+    /// Whether a pass over the cache is currently running
+    pub running: bool,
+    /// Number of passes over the cache that have completed
+    pub passes_completed: u64,
+    /// Number of entries verified by the current pass (or the last one, if none is running)
+    pub pass_verified: u64,
+    /// Number of entries the current pass (or the last one, if none is running) had to verify
+    pub pass_total: u64,
+    /// Keys of the corrupted entries found by the current pass (or the last one, if none is
+    /// running)
+    pub corrupted: Vec<Vec<u8>>,
+    /// Total number of entries verified over every pass
+    pub entries_verified: u64,
+    /// Total number of bytes verified over every pass
+    pub bytes_verified: u64,
+    /// Total number of corrupted entries that were removed, see [`ScrubAction::Remove`]
+    pub entries_removed: u64,
+    /// Total number of corrupted entries that were quarantined, see
+    /// [`ScrubAction::Quarantine`]
+    pub entries_quarantined: u64,
+    /// Total number of entries that couldn't be verified because their integrity algorithm isn't
+    /// [available](crate::HashAlgorithm::is_available) in this build. These are never removed
+    /// or quarantined.
+    pub unverifiable: u64,
+    /// Total number of entries that couldn't be verified because of an error
+    pub errors: u64,
+    /// When the last pass completed
+    pub last_pass_finished: Option<SystemTime>,
+}
+
+/// The result of verifying a single entry
+enum Verified {
+    /// The entry was removed (or replaced) before it could be verified
+    Skipped,
+    /// The entry's integrity algorithm isn't available, so it can't be verified
+    Unverifiable,
+    Valid(u64),
+    Corrupted {
+        size: u64,
+        removed: bool,
+        quarantined: bool,
+    },
+}
+
+/// Spawns the background scrubber for the cache described by `opts`, returning its status.
+///
+/// The task only holds a weak reference to `meta`, so it stops by itself once the cache is
+/// dropped.
+pub(super) fn spawn(
+    meta: &Arc<MetaDb>,
+    mem: &Arc<MemCache>,
+    locks: &Arc<KeyLocks>,
+    access: Option<&Arc<AccessBuffer>>,
+    opts: Options,
+    mut scrub: ScrubOptions,
+) -> Arc<Mutex<ScrubStatus>> {
+    let status = Arc::new(Mutex::new(ScrubStatus::default()));
+    let (meta, task_status) = (Arc::downgrade(meta), Arc::clone(&status));
+    let (mem, locks, access) = (Arc::clone(mem), Arc::clone(locks), access.cloned());
+    // read-only caches can't remove anything
+    if opts.read_only {
+        scrub.action = ScrubAction::Report;
+    }
+    tokio::spawn(async move {
+        let entries = Entries {
+            mem: &mem,
+            locks: &locks,
+            access: access.as_deref(),
+            opts: &opts,
+        };
+        while run_pass(&meta, &entries, &scrub, &task_status).await {
+            tokio::time::sleep(scrub.interval).await;
+        }
+    });
+    status
+}
+
+/// The parts of a cache the scrubber needs besides its metadata, which it only holds weakly
+struct Entries<'a> {
+    mem: &'a MemCache,
+    locks: &'a KeyLocks,
+    access: Option<&'a AccessBuffer>,
+    opts: &'a Options,
+}
+
+/// Runs a single pass over every entry, returning `false` if the cache was dropped in the
+/// meantime.
+async fn run_pass(
+    meta: &Weak<MetaDb>,
+    entries: &Entries<'_>,
+    scrub: &ScrubOptions,
+    status: &Mutex<ScrubStatus>,
+) -> bool {
+    // only the keys are collected up front, so the database isn't kept open by an iterator
+    let keys = {
+        let Some(meta) = meta.upgrade() else {
+            return false;
+        };
+        meta.keys().filter_map(Result::ok).collect::<Vec<_>>()
+    };
+    {
+        let mut status = status.lock();
+        status.running = true;
+        status.pass_verified = 0;
+        status.pass_total = keys.len() as u64;
+        status.corrupted.clear();
+    }
+
+    let started = Instant::now();
+    let mut pass_bytes = 0;
+    for key in keys {
+        let Some(meta) = meta.upgrade() else {
+            return false;
+        };
+        let verified = verify_entry(&meta, entries, scrub.action, &key).await;
+        drop(meta);
+
+        let size = record(&mut status.lock(), key, verified);
+        pass_bytes += size.unwrap_or(0);
+
+        // sleep until the average read rate of this pass is back under the limit
+        if let Some(limit) = scrub.max_bytes_per_sec.filter(|&l| l > 0) {
+            let target = Duration::from_secs_f64(pass_bytes as f64 / limit as f64);
+            if let Some(ahead) = target.checked_sub(started.elapsed()) {
+                tokio::time::sleep(ahead).await;
+            }
+        }
+    }
+
+    let mut status = status.lock();
+    status.running = false;
+    status.passes_completed += 1;
+    status.last_pass_finished = Some(SystemTime::now());
+    true
+}
+
+/// Records the result of verifying the entry `key` in `status`, returning the number of bytes
+/// that were read if it was verified
+fn record(status: &mut ScrubStatus, key: Vec<u8>, verified: Result<Verified>) -> Option<u64> {
+    status.pass_verified += 1;
+    let size = match verified {
+        Ok(Verified::Skipped) => None,
+        Ok(Verified::Unverifiable) => {
+            status.unverifiable += 1;
+            None
+        }
+        Ok(Verified::Valid(size)) => Some(size),
+        Ok(Verified::Corrupted {
+            size,
+            removed,
+            quarantined,
+        }) => {
+            status.corrupted.push(key);
+            status.entries_removed += removed as u64;
+            status.entries_quarantined += quarantined as u64;
+            Some(size)
+        }
+        Err(_) => {
+            status.errors += 1;
+            None
+        }
+    };
+    if let Some(size) = size {
+        status.entries_verified += 1;
+        status.bytes_verified += size;
+    }
+    size
+}
+
+/// Hashes the file at `path` with `alg` in chunks, returning its size and digest (`None` if the
+/// algorithm isn't available), or `None` if the file doesn't exist
+pub(super) async fn digest_file(
+    path: &std::path::Path,
+    alg: HashAlgorithm,
+) -> Result<Option<(u64, Option<Vec<u8>>)>> {
+    let mut file = match afs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ForcepError::Io(e)),
+    };
+    let mut hasher = alg.hasher();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf).await.map_err(ForcepError::Io)?;
+        if read == 0 {
+            break;
+        }
+        size += read as u64;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buf[..read]);
+        }
+    }
+    Ok(Some((size, hasher.map(|hasher| hasher.finalize()))))
+}
+
+/// Verifies the data of the entry `key` against its integrity hash, removing or quarantining it
+/// if it's corrupted and `action` says so
+async fn verify_entry(
+    meta: &MetaDb,
+    entries: &Entries<'_>,
+    action: ScrubAction,
+    key: &[u8],
+) -> Result<Verified> {
+    let Some(expected) = meta.get_metadata_opt(key)? else {
+        return Ok(Verified::Skipped);
+    };
+    // a mismatch can't be told apart from an entry written by a build with other features
+    if !expected.get_integrity_algorithm().is_available() {
+        return Ok(Verified::Unverifiable);
+    }
+    let path = entries.opts.path_from_key(key);
+    // missing files are found by `Cache::check`, they have nothing to verify
+    let Some((size, digest)) = digest_file(&path, expected.get_integrity_algorithm()).await? else {
+        return Ok(Verified::Skipped);
+    };
+    if digest.is_some_and(|digest| digest == expected.get_integrity_digest()) {
+        return Ok(Verified::Valid(size));
+    }
+
+    if action == ScrubAction::Report {
+        return Ok(Verified::Corrupted {
+            size,
+            removed: false,
+            quarantined: false,
+        });
+    }
+
+    // the entry can't be replaced between the check below and its removal
+    let _guard = entries.locks.lock(key).await;
+    // the entry may have been replaced while it was read
+    match meta.get_metadata_opt(key)? {
+        Some(current) if current.get_version() == expected.get_version() => {}
+        _ => return Ok(Verified::Skipped),
+    }
+    let mut quarantined = false;
+    if action == ScrubAction::Quarantine {
+        quarantined = quarantine::move_in(
+            meta,
+            entries.mem,
+            entries.access,
+            entries.opts,
+            key,
+            &expected,
+        )
+        .await?
+        .is_some();
+    } else {
+        let removed =
+            super::remove_entry(meta, entries.mem, entries.access, entries.opts, key).await;
+        match removed {
+            Ok(_) | Err(ForcepError::NotFound) | Err(ForcepError::MetaNotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Verified::Corrupted {
+        size,
+        removed: action == ScrubAction::Remove,
+        quarantined,
+    })
+}
@@ -0,0 +1,100 @@
+use super::Options;
+use crate::{ForcepError, MetaDb, Result};
+use std::fs;
+use std::io;
+use std::path;
+use std::time::SystemTime;
+
+/// Number of attempts at copying the metadata database without it changing in the meantime
+const COPY_ATTEMPTS: usize = 5;
+
+/// A private copy of the metadata database of a cache, which is removed when dropped.
+///
+/// Read-only caches open a snapshot when the backend has no read-only mode (or it's opened for
+/// writing by another cache), since the database can only be opened by a single cache at a time.
+#[derive(Debug)]
+pub(super) struct Snapshot {
+    dir: path::PathBuf,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Opens the metadata database of the cache described by `opts` without taking ownership of it,
+/// returning the snapshot it was copied to (if any).
+///
+/// Backends with a read-only mode are opened in place if they don't need to be migrated.
+/// Otherwise the database is copied, and the copy is only used if the database didn't change
+/// while it was copied. Backends that aren't stored inside the cache directory are used as they
+/// are, without running any migrations.
+pub(super) fn open(opts: &Options) -> Result<(MetaDb, Option<Snapshot>)> {
+    let Some(storage) = opts.meta_backend.storage_path(&opts.path) else {
+        let meta = MetaDb::new(opts.meta_backend.open(&opts.path)?, opts.hash_alg)?;
+        return Ok((meta, None));
+    };
+    if let Some(backend) = opts.meta_backend.open_read_only(&opts.path)? {
+        let meta = MetaDb::new(backend, opts.hash_alg)?;
+        if meta.is_migrated()? {
+            return Ok((meta, None));
+        }
+    }
+
+    let snapshot = Snapshot {
+        dir: crate::tmp::tmppath_in(&std::env::temp_dir()),
+    };
+    // the copy keeps the layout of the cache directory, so the backend can be opened from it
+    let copy = snapshot.dir.join(storage.file_name().unwrap_or_default());
+    for _ in 0..COPY_ATTEMPTS {
+        let before = fingerprint(&storage)?;
+        let _ = fs::remove_dir_all(&snapshot.dir);
+        copy_all(&storage, &copy)?;
+        // a copy taken while the database was written to may be torn
+        if fingerprint(&storage)? != before {
+            continue;
+        }
+        let meta = MetaDb::new(opts.meta_backend.open(&snapshot.dir)?, opts.hash_alg)?;
+        // the copy is private, so it can be upgraded without touching the original
+        meta.migrate()?;
+        return Ok((meta, Some(snapshot)));
+    }
+    Err(ForcepError::Io(io::Error::new(
+        io::ErrorKind::ResourceBusy,
+        "the metadata database kept changing while it was copied",
+    )))
+}
+
+/// The size and modification time of every file at `path` (recursively), which changes whenever
+/// any of them is written to
+fn fingerprint(path: &path::Path) -> Result<Vec<(path::PathBuf, u64, Option<SystemTime>)>> {
+    let meta = fs::metadata(path).map_err(ForcepError::Io)?;
+    if !meta.is_dir() {
+        return Ok(vec![(path.to_path_buf(), meta.len(), meta.modified().ok())]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path).map_err(ForcepError::Io)? {
+        let entry = entry.map_err(ForcepError::Io)?;
+        files.extend(fingerprint(&entry.path())?);
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Copies the file or directory (recursively) at `from` to `to`, creating any missing parent
+/// directories
+fn copy_all(from: &path::Path, to: &path::Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(ForcepError::Io)?;
+    }
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ()).map_err(ForcepError::Io);
+    }
+    fs::create_dir_all(to).map_err(ForcepError::Io)?;
+    for entry in fs::read_dir(from).map_err(ForcepError::Io)? {
+        let entry = entry.map_err(ForcepError::Io)?;
+        copy_all(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
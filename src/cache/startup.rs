@@ -0,0 +1,74 @@
+use crate::{ForcepError, Result};
+use std::io;
+use std::path;
+use std::time::{Duration, SystemTime};
+use tokio::fs as afs;
+
+/// Information about the work done while building a [`Cache`](super::Cache).
+///
+/// Retrieved with [`Cache::startup_report`](super::Cache::startup_report).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StartupReport {
+    /// Number of orphaned temporary files that were removed from the cache directory
+    pub temp_files_removed: u64,
+    /// Total size (in bytes) of the removed temporary files
+    pub temp_bytes_reclaimed: u64,
+    /// If the metadata database was corrupted and has been rebuilt, the path the corrupted
+    /// database was moved to. See
+    /// [`CacheBuilder::rebuild_index_on_failure`](crate::CacheBuilder::rebuild_index_on_failure).
+    pub corrupt_index: Option<std::path::PathBuf>,
+    /// Number of entries whose metadata was restored by rebuilding the metadata database
+    pub entries_restored: u64,
+    /// Number of interrupted writes, removals, quarantines and transactions that were completed
+    /// or undone, so the files and metadata of their entries agree again
+    pub intents_replayed: u64,
+}
+
+/// Removes every temporary file in `dir` that hasn't been modified for at least `min_age`,
+/// recording what was removed in `report`.
+///
+/// Temporary files are left behind when the process dies in the middle of a write or removal.
+/// Younger files are skipped, since they may belong to an operation of another process that is
+/// still in progress.
+pub(super) async fn sweep_temp_files(
+    dir: &path::Path,
+    min_age: Duration,
+    report: &mut StartupReport,
+) -> Result<()> {
+    let mut entries = afs::read_dir(dir).await.map_err(ForcepError::Io)?;
+    let now = SystemTime::now();
+    while let Some(entry) = entries.next_entry().await.map_err(ForcepError::Io)? {
+        if !entry
+            .file_name()
+            .to_str()
+            .is_some_and(crate::tmp::is_tmpname)
+        {
+            continue;
+        }
+        let meta = match entry.metadata().await {
+            Ok(meta) => meta,
+            // removed by someone else in the meantime
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(ForcepError::Io(e)),
+        };
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .unwrap_or_default();
+        if !meta.is_file() || age < min_age {
+            continue;
+        }
+
+        match afs::remove_file(entry.path()).await {
+            Ok(()) => {
+                report.temp_files_removed += 1;
+                report.temp_bytes_reclaimed += meta.len();
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(ForcepError::Io(e)),
+        }
+    }
+    Ok(())
+}
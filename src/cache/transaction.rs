@@ -0,0 +1,394 @@
+use super::{Cache, Options, WriteOptions, journal, storage_full};
+use crate::{ForcepError, Intent, Metadata, Result, TxChange, TxOp};
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::io;
+use std::path;
+use tokio::fs as afs;
+
+/// A single staged operation of a [`Transaction`]
+#[derive(Debug)]
+enum Op {
+    Write {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        opts: WriteOptions,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+}
+
+/// A group of writes and removals that are applied to a [`Cache`] atomically, created with
+/// [`Cache::transaction`].
+///
+/// Nothing is changed until [`commit`](Self::commit) is called. Committing first writes the
+/// data of every write to temporary files, and then moves all files into place and updates the
+/// metadata of every entry in a single batch. Reads of the entries of the transaction wait while
+/// their files are moved, so readers see either every change of the transaction or none of them.
+/// If any step fails, every file that was already moved is put back and the metadata is left
+/// untouched.
+///
+/// The transaction is recorded in the journal before any file is moved, so if the process dies
+/// before the metadata is updated, the moved files are put back when the cache is built again.
+///
+/// Operations are applied in the order they were added, so later operations on the same key
+/// override earlier ones.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::Cache;
+///
+/// let cache = Cache::new("./cache")
+///     .build()
+///     .await
+///     .unwrap();
+/// # cache.write(b"OLD_MANIFEST", b"{}").await.unwrap();
+///
+/// cache
+///     .transaction()
+///     .write(b"BLOB", b"Hello World")
+///     .write(b"MANIFEST", b"{\"blobs\": [\"BLOB\"]}")
+///     .remove(b"OLD_MANIFEST")
+///     .commit()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+#[must_use = "a transaction does nothing until it is committed"]
+pub struct Transaction<'a> {
+    cache: &'a Cache,
+    ops: Vec<Op>,
+}
+
+/// A file system change made while publishing a transaction, so it can be undone
+#[derive(Debug)]
+struct Published {
+    path: path::PathBuf,
+    /// Where the previous file at `path` was moved to, if there was one
+    backup: Option<path::PathBuf>,
+    /// The staged file that was moved to `path`, if any
+    placed: Option<path::PathBuf>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(cache: &'a Cache) -> Self {
+        Self {
+            cache,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Adds a write of `value` to the entry `key`, like [`Cache::write`].
+    pub fn write<K: AsRef<[u8]>, V: AsRef<[u8]>>(self, key: K, value: V) -> Self {
+        self.write_with(key, value, WriteOptions::default())
+    }
+
+    /// Adds a write of `value` to the entry `key` using the provided [`WriteOptions`], like
+    /// [`Cache::write_with`].
+    ///
+    /// If [`WriteOptions::if_version`] is set and the entry has a different version when the
+    /// transaction is committed, the whole transaction fails with
+    /// [`ForcepError::VersionMismatch`].
+    pub fn write_with<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        mut self,
+        key: K,
+        value: V,
+        opts: WriteOptions,
+    ) -> Self {
+        self.ops.push(Op::Write {
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+            opts,
+        });
+        self
+    }
+
+    /// Adds a removal of the entry `key`, like [`Cache::remove`].
+    ///
+    /// If the entry doesn't exist when the transaction is committed, the whole transaction fails
+    /// with [`ForcepError::MetaNotFound`].
+    pub fn remove<K: AsRef<[u8]>>(mut self, key: K) -> Self {
+        self.ops.push(Op::Remove {
+            key: key.as_ref().to_vec(),
+        });
+        self
+    }
+
+    /// Applies every operation of the transaction atomically, returning the [`Metadata`] of each
+    /// operation in order: the new metadata for writes, and the removed metadata for removals.
+    ///
+    /// If this fails, none of the operations are applied. If the disk is full, this fails with
+    /// [`ForcepError::StorageFull`], see
+    /// [`CacheBuilder::emergency_evictor`](crate::CacheBuilder::emergency_evictor).
+    pub async fn commit(self) -> Result<Vec<Metadata>> {
+        let cache = self.cache;
+        cache.ensure_writable()?;
+
+        // write the data of every write to temporary files first, which is the slow part
+        let mut staged = Vec::with_capacity(self.ops.len());
+        for op in &self.ops {
+            let tmp_path = match op {
+                Op::Write { value, .. } => match cache.stage_or_evict(value).await {
+                    Ok(tmp_path) => Some(tmp_path),
+                    Err(e) => {
+                        remove_staged(staged.iter().flatten()).await;
+                        return Err(e);
+                    }
+                },
+                Op::Remove { .. } => None,
+            };
+            staged.push(tmp_path);
+        }
+
+        // other writes and removals of the same entries wait until the transaction is done
+        let _guards = cache.locks.lock_all(self.ops.iter().map(op_key)).await;
+        // a full disk can fail any of the steps after staging as well
+        let (results, published) = match self.prepare(&staged).await {
+            Ok((tx_ops, changes)) => self
+                .publish(tx_ops, &changes, &staged)
+                .await
+                .map_err(storage_full)?,
+            Err(e) => {
+                remove_staged(staged.iter().flatten()).await;
+                return Err(storage_full(e));
+            }
+        };
+
+        // the previous values are only deleted once the transaction can't be undone anymore
+        let mut synced = HashSet::new();
+        for change in published {
+            if let Some(backup) = change.backup {
+                let _ = afs::remove_file(backup).await;
+            }
+            if synced.insert(change.path.parent().map(path::Path::to_path_buf)) {
+                let synced = cache.opts.durability.sync_parent(&change.path).await;
+                synced.map_err(storage_full)?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Creates the metadata operations of the transaction and the changes it makes to the files
+    /// of its entries, creating the directories of the entries along the way.
+    async fn prepare(
+        &self,
+        staged: &[Option<path::PathBuf>],
+    ) -> Result<(Vec<TxOp>, Vec<TxChange>)> {
+        let cache = self.cache;
+        let mut tx_ops = Vec::with_capacity(self.ops.len());
+        let mut changes = Vec::with_capacity(self.ops.len());
+        for (op, tmp_path) in self.ops.iter().zip(staged) {
+            let key = op_key(op);
+            let path = cache.path_from_key(key);
+            if let Some(parent) = path.parent() {
+                afs::create_dir_all(parent).await.map_err(ForcepError::Io)?;
+            }
+            tx_ops.push(match op {
+                Op::Write { key, value, opts } => TxOp::Insert {
+                    key: key.clone(),
+                    meta: cache.meta.new_metadata(
+                        value,
+                        opts.attributes.clone(),
+                        opts.tags.iter().cloned().collect(),
+                    ),
+                    expected_version: opts.if_version,
+                },
+                Op::Remove { key } => TxOp::Remove { key: key.clone() },
+            });
+            changes.push(TxChange {
+                key: key.to_vec(),
+                tmp: tmp_path.as_deref().map(journal::tmp_name).transpose()?,
+                backup: journal::tmp_name(&crate::tmp::tmppath_in(&cache.opts.path))?,
+            });
+        }
+        Ok((tx_ops, changes))
+    }
+
+    /// Records the transaction in the journal, moves every staged file into place and applies the
+    /// metadata of every operation in a single batch, undoing the moves if anything fails.
+    ///
+    /// Reads of the changed entries wait until the files and the metadata are both updated.
+    /// Staged files are removed if this fails, unless the journal still needs them to undo the
+    /// transaction.
+    async fn publish(
+        &self,
+        tx_ops: Vec<TxOp>,
+        changes: &[TxChange],
+        staged: &[Option<path::PathBuf>],
+    ) -> Result<(Vec<Metadata>, Vec<Published>)> {
+        let cache = self.cache;
+        let intent = cache.begin_intent(&Intent::Transaction {
+            changes: changes.to_vec(),
+        });
+        let intent = match intent {
+            Ok(intent) => intent,
+            Err(e) => {
+                remove_staged(staged.iter().flatten()).await;
+                return Err(e);
+            }
+        };
+        // reads of the entries wait until the files and the metadata are both updated. other
+        // changes to them already wait for the locks of the entries.
+        let readers = cache
+            .locks
+            .exclude_readers(self.ops.iter().map(op_key))
+            .await;
+        let mut published = Vec::with_capacity(staged.len());
+        let mut moved = Ok(());
+        for (change, tmp_path) in changes.iter().zip(staged) {
+            let path = cache.path_from_key(&change.key);
+            let backup = cache.opts.path.join(&change.backup);
+            match move_into_place(path, backup, tmp_path.as_deref()).await {
+                Ok(change) => published.push(change),
+                Err(e) => {
+                    moved = Err(e);
+                    break;
+                }
+            }
+        }
+        // the batch ends the intent
+        let res = moved.and_then(|()| cache.meta.apply_transaction(tx_ops, intent));
+        let mut results = match res {
+            Ok(results) => results,
+            Err(e) => {
+                undo(&published).await;
+                // if the moves couldn't all be undone, the intent is kept so they're undone the
+                // next time the cache is built
+                if is_undone(&cache.opts, changes).await {
+                    cache.meta.end_intent(intent)?;
+                    remove_staged(staged.iter().flatten()).await;
+                }
+                return Err(e);
+            }
+        };
+
+        // the memory cache is updated before reads can continue
+        for (op, meta) in self.ops.iter().zip(&mut results) {
+            match op {
+                Op::Write { key, value, .. } => {
+                    if !cache.mem.is_nil() {
+                        cache.mem.put(key, Bytes::from(value.clone()));
+                    }
+                    if let Some(access) = &cache.access {
+                        access.discard(key);
+                    }
+                }
+                Op::Remove { key } => {
+                    cache.mem.remove(key);
+                    if let Some(access) = &cache.access {
+                        access.merge_into(key, meta);
+                        access.discard(key);
+                    }
+                }
+            }
+        }
+        drop(readers);
+        Ok((results, published))
+    }
+}
+
+/// The key of the entry `op` changes
+fn op_key(op: &Op) -> &[u8] {
+    match op {
+        Op::Write { key, .. } | Op::Remove { key } => key,
+    }
+}
+
+/// Moves the current file at `path` (if any) to `backup`, and then moves the staged file at
+/// `tmp_path` (if any) to `path`.
+async fn move_into_place(
+    path: path::PathBuf,
+    backup: path::PathBuf,
+    tmp_path: Option<&path::Path>,
+) -> Result<Published> {
+    let backup = match afs::rename(&path, &backup).await {
+        Ok(()) => Some(backup),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(ForcepError::Io(e)),
+    };
+    let mut change = Published {
+        path,
+        backup,
+        placed: None,
+    };
+    if let Some(tmp_path) = tmp_path {
+        if let Err(e) = afs::rename(tmp_path, &change.path).await {
+            undo(std::slice::from_ref(&change)).await;
+            return Err(ForcepError::Io(e));
+        }
+        change.placed = Some(tmp_path.to_path_buf());
+    }
+    Ok(change)
+}
+
+/// Undoes every change in `published`, newest first, so every file ends up where it was before.
+///
+/// Placed files are moved back to their staged path instead of being deleted, so the journal
+/// still describes where every file is.
+async fn undo(published: &[Published]) {
+    for change in published.iter().rev() {
+        if let Some(tmp_path) = &change.placed {
+            let _ = afs::rename(&change.path, tmp_path).await;
+        }
+        if let Some(backup) = &change.backup {
+            let _ = afs::rename(backup, &change.path).await;
+        }
+    }
+}
+
+/// Whether every file of the transaction `changes` is back where it was before it was published
+async fn is_undone(opts: &Options, changes: &[TxChange]) -> bool {
+    for change in changes {
+        let exists = |name: &str| afs::try_exists(opts.path.join(name));
+        let staged = match &change.tmp {
+            Some(tmp) => exists(tmp).await.unwrap_or(false),
+            None => true,
+        };
+        if !staged || exists(&change.backup).await.unwrap_or(true) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Undoes the interrupted transaction `changes`, whose metadata was never updated, by moving
+/// every file back to where it was before, newest first. Staged files are removed afterwards.
+pub(super) async fn replay(opts: &Options, changes: &[TxChange]) -> Result<()> {
+    for change in changes.iter().rev() {
+        let path = opts.path_from_key(&change.key);
+        // a staged file that is gone was moved to the entry
+        if let Some(tmp) = &change.tmp {
+            let tmp_path = opts.path.join(tmp);
+            let staged = afs::try_exists(&tmp_path).await.map_err(ForcepError::Io)?;
+            if !staged {
+                rename_if_exists(&path, &tmp_path).await?;
+            }
+        }
+        rename_if_exists(&opts.path.join(&change.backup), &path).await?;
+    }
+    for tmp in changes.iter().filter_map(|change| change.tmp.as_ref()) {
+        journal::remove_if_exists(&opts.path.join(tmp)).await?;
+    }
+    Ok(())
+}
+
+/// Moves the file at `from` to `to`, ignoring a missing `from`
+async fn rename_if_exists(from: &path::Path, to: &path::Path) -> Result<()> {
+    match afs::rename(from, to).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ForcepError::Io(e)),
+    }
+}
+
+/// Removes staged temporary files, ignoring files that were already moved
+async fn remove_staged<'a, I: Iterator<Item = &'a path::PathBuf>>(paths: I) {
+    for path in paths {
+        let _ = afs::remove_file(path).await;
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{AttributeValue, Attributes};
+use std::collections::BTreeSet;
+
+/// Options for a single [`Cache::write_with`](super::Cache::write_with) operation.
+///
+/// These options describe extra information that should be stored alongside the entry, such as
+/// user-defined attributes and tags.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use forceps::{Cache, WriteOptions};
+///
+/// let cache = Cache::new("./cache")
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let opts = WriteOptions::new()
+///     .attribute("content-type", "text/plain")
+///     .attribute("producer-version", 3);
+/// let meta = cache.write_with(b"MY_KEY", b"Hello World", opts).await.unwrap();
+/// assert_eq!(
+///     meta.get_attribute("content-type").and_then(|v| v.as_str()),
+///     Some("text/plain")
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub(crate) attributes: Attributes,
+    pub(crate) tags: BTreeSet<String>,
+    pub(crate) if_version: Option<u64>,
+}
+
+impl WriteOptions {
+    /// Creates a new [`WriteOptions`] with nothing extra set. Writing with these options is
+    /// identical to calling [`Cache::write`](super::Cache::write).
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a user-defined attribute that will be stored in the entry's metadata.
+    ///
+    /// Setting the same `name` twice will overwrite the previous value.
+    pub fn attribute<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<AttributeValue>,
+    {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
+
+    /// Replaces all of the user-defined attributes that will be stored in the entry's metadata.
+    pub fn attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Adds the entry to the group `tag`.
+    ///
+    /// Tagged entries can be listed with [`Cache::keys_with_tag`] and removed all at once with
+    /// [`Cache::invalidate_tag`].
+    ///
+    /// [`Cache::keys_with_tag`]: super::Cache::keys_with_tag
+    /// [`Cache::invalidate_tag`]: super::Cache::invalidate_tag
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    /// Only performs the write if the current [version](crate::Metadata::get_version) of the
+    /// entry is `version`, otherwise the write fails with
+    /// [`ForcepError::VersionMismatch`](crate::ForcepError::VersionMismatch) and the entry is left
+    /// untouched.
+    ///
+    /// Versions start at `1`, so a `version` of `0` only writes the entry if it doesn't exist yet.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use forceps::{Cache, WriteOptions};
+    ///
+    /// let cache = Cache::new("./cache")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// # cache.write(b"MY_KEY", b"Hello World").await.unwrap();
+    /// let version = cache.read_metadata(b"MY_KEY").unwrap().get_version();
+    /// let opts = WriteOptions::new().if_version(version);
+    /// let meta = cache.write_with(b"MY_KEY", b"Hello", opts).await.unwrap();
+    /// assert_eq!(meta.get_version(), version + 1);
+    /// # }
+    /// ```
+    pub fn if_version(mut self, version: u64) -> Self {
+        self.if_version = Some(version);
+        self
+    }
+}
@@ -1,5 +1,5 @@
-use crate::{Cache, ForcepError, Metadata};
-use std::cmp;
+use crate::{Cache, ForcepError, TimeIndex};
+use std::pin::Pin;
 
 /// A trait that represents a structure or enum that can evict items out of a [`Cache`] instance.
 ///
@@ -50,16 +50,37 @@ pub trait Evictor {
     fn evict(&self, cache: &Cache) -> impl Future<Output = Result<u64, Self::Err>>;
 }
 
+/// An [`Evictor`] that a [`Cache`] can run by itself when a write fails because the disk is
+/// full, see [`CacheBuilder::emergency_evictor`](crate::CacheBuilder::emergency_evictor).
+///
+/// This trait is sealed, and implemented for the ready-made evictors of this module.
+pub trait EmergencyEvictor: Evictor + sealed::Sealed {}
+
+mod sealed {
+    use super::*;
+
+    /// The future of an evictor run by the cache itself
+    pub type EvictFuture<'a> = Pin<Box<dyn Future<Output = Result<u64, ForcepError>> + Send + 'a>>;
+
+    /// Runs an evictor behind a trait object, which [`Evictor`] can't be used as
+    pub trait Sealed: std::fmt::Debug + Send + Sync + 'static {
+        fn evict_boxed<'a>(&'a self, cache: &'a Cache) -> EvictFuture<'a>;
+    }
+}
+pub(crate) use sealed::Sealed as DynEvictor;
+
 /// A trait for evictors that will evict items until a minimum size is met
 ///
-/// This trait default implements `evict_to_min_size`, and requires `batch_size` and `min_size`.
+/// This trait default implements `evict_to_min_size`, and requires `index`, `batch_size` and
+/// `min_size`.
 trait MinSzEvictor {
-    type Candidate: EvictCandidate + Send + Sync;
+    /// The time index that orders entries by how they should be evicted, oldest first.
+    const INDEX: TimeIndex;
 
     /// Getter for the batch size of the eviction. Recommended to use `#[inline]`
     ///
-    /// The batch size indicates how many eviction candidates should be found for each eviction
-    /// loop. Bigger values help bigger evictions.
+    /// The batch size indicates how many eviction candidates should be read from the index for
+    /// each eviction loop. Bigger values help bigger evictions.
     fn batch_size(&self) -> usize;
     /// The minimum size (in bytes) to evict to.
     fn min_size(&self) -> u64;
@@ -69,129 +90,45 @@ trait MinSzEvictor {
     /// Configuration is done via the `batch_size` and `min_size` function implementions
     async fn evict_to_min_size(&self, cache: &Cache) -> Result<u64, ForcepError> {
         let mut evicted = 0;
+        let mut total_size = cache.total_size()?;
 
         // run the evictor in a loop so if it runs out of candidates, it can just jump back and
-        // look for a new set of candidates
-        'evictor: loop {
-            let (mut total_size, scan) =
-                find_evict_candidates::<Self::Candidate>(cache, self.batch_size())?;
+        // read the next set of candidates from the index
+        'evictor: while total_size > self.min_size() {
+            let candidates = cache.oldest_keys(Self::INDEX, self.batch_size())?;
             // break if there are no candidates (cache is completely empty)
-            if scan.is_empty() {
+            if candidates.is_empty() {
                 break;
             }
 
             // loop through all candidates and remove them one-by-one until it meets size
             // requirement
             // TODO: maybe in the future this can batched into FuturesUnordered?
-            for e in &scan {
+            let mut removed_any = false;
+            for key in &candidates {
                 if total_size <= self.min_size() {
                     break 'evictor;
                 }
 
                 // this almost certainly won't fail, and if it does we should treat it as fatal
-                // (pushed up the stack)
-                let meta = cache.remove(e.key()).await?;
-                total_size -= meta.get_size();
+                // (pushed up the stack). entries locked by a `KeyGuard` are skipped.
+                let Some(meta) = cache.evict_entry(key).await? else {
+                    continue;
+                };
+                removed_any = true;
+                total_size = total_size.saturating_sub(meta.get_size());
                 evicted += meta.get_size();
             }
+            // every candidate is locked, so the next batch would be the same one
+            if !removed_any {
+                break;
+            }
         }
 
         Ok(evicted)
     }
 }
 
-/// A trait that represents a candidate for eviction. Used as a generalization for
-/// [`find_evict_candidates`]
-///
-/// A structure that implements this trait can be created using
-/// [`from_meta`](EvictCandidate::from_meta). To check whether it should be evicted over another
-/// [`EvictCandidate`], [`should_evict_over`](EvictCandidate::should_evict_over) can be used.
-trait EvictCandidate {
-    /// Creates `Self` based on the metadata entry.
-    fn from_meta(key: Vec<u8>, meta: Metadata) -> Self;
-    /// The key that represents the cache entry.
-    fn key(&self) -> &[u8];
-    /// An ordering that represents whether this candidate should be evicted *before* the `other`
-    /// candidate.
-    ///
-    /// [`cmp::Ordering::Greater`] represents that this candidate should be evicted over `other`.
-    fn should_evict_over(&self, other: &Self) -> cmp::Ordering;
-}
-
-/// Finds the total size of the cache and a vector of eviction candidates.
-///
-/// The eviction candidates is an in-order list of candidates that should be removed from the
-/// cache. These are selected based on the priority to evict, which is determined by
-/// [`EvictCandidate::should_evict_over`].
-///
-/// This function runs at approx. `O(n * batch)` where `n` is the number of metadata entries, and
-/// `batch` is the variable provided.
-fn find_evict_candidates<E: EvictCandidate>(
-    cache: &Cache,
-    batch: usize,
-) -> Result<(u64, Vec<E>), ForcepError> {
-    let mut total_sz = 0;
-    let mut entries = Vec::with_capacity(batch);
-
-    for result in cache.metadata_iter() {
-        // if any weird errors happen with the iter, then we should treat them as fatal and just
-        // push them up the stack
-        let (key, meta) = result?;
-
-        // increment the total size counter
-        total_sz += meta.get_size();
-
-        // if the `entries` vector isn't filled, just insert the entry
-        let entry = E::from_meta(key, meta);
-        if entries.len() < batch {
-            entries.push(entry);
-            continue;
-        }
-
-        // `entries` vector is completely filled, so swap the values if this current metadata entry
-        // should be evicted sooner than the one stored
-        //
-        // this slowly weeds out all entries of metadata until there are only the ones left that
-        // should be evicted first
-        if let Some((index, _)) = entries
-            .iter()
-            .enumerate()
-            .find(|(_, e)| entry.should_evict_over(e) == cmp::Ordering::Greater)
-        {
-            entries[index] = entry;
-        }
-    }
-
-    // sort the entries so they're in order by how they should be evicted
-    entries.sort_unstable_by(|a, b| b.should_evict_over(a));
-    Ok((total_sz, entries))
-}
-
-/// [`EvictCandidate`] implementation for the Least Recently Used eviction algorithm.
-#[derive(Debug)]
-struct LruEc {
-    key: Vec<u8>,
-    last_access: u64,
-}
-impl EvictCandidate for LruEc {
-    #[inline]
-    fn from_meta(key: Vec<u8>, meta: Metadata) -> Self {
-        Self {
-            key,
-            last_access: meta.get_last_accessed_raw(),
-        }
-    }
-    #[inline]
-    fn key(&self) -> &[u8] {
-        &self.key
-    }
-    #[inline]
-    fn should_evict_over(&self, other: &Self) -> cmp::Ordering {
-        // `other > self` would result in cmp::Ordering::Greater
-        other.last_access.cmp(&self.last_access)
-    }
-}
-
 /// Least Recently Used eviction algorithm for a [`Cache`]
 ///
 /// This algorithm will evict items based on when they were lasted `read` from the [`Cache`]. It
@@ -205,8 +142,9 @@ impl EvictCandidate for LruEc {
 ///
 /// ## O(?) & Async
 ///
-/// This eviction algorithm has no guarantee on being fast or efficient at all. It is expected that
-/// this algorithm is called infrequently, only when absolutely needed.
+/// Candidates are read in order from a secondary index of the metadata database, so finding the
+/// next `batch` candidates is roughly `O(log n + batch)`. The total size of the cache is read from
+/// a counter, so no pass over the whole metadata is required.
 ///
 /// This algorithm also contains blocking calls in an `async` context, mainly metadata iterations
 /// and lookups. The reason for the `async` context is for the actual removals from cache.
@@ -220,9 +158,11 @@ impl EvictCandidate for LruEc {
 ///
 /// **Batch Size**
 ///
-/// To create one batch, an entire iteration over the metadata must be performed. However, smaller
-/// values means that there is less checking/sorting for each batch found. Higher values should be
-/// used if you're expecting to evict more items at a time.
+/// The number of candidates read from the index at a time. Higher values should be used if you're
+/// expecting to evict more items at a time.
+///
+/// Entries locked by a [`KeyGuard`](crate::KeyGuard) are skipped. If a whole batch of candidates
+/// is locked, eviction stops early.
 ///
 /// # Examples
 ///
@@ -271,7 +211,7 @@ impl LruEvictor {
     }
 }
 impl MinSzEvictor for LruEvictor {
-    type Candidate = LruEc;
+    const INDEX: TimeIndex = TimeIndex::LastAccessed;
 
     #[inline]
     fn min_size(&self) -> u64 {
@@ -288,28 +228,12 @@ impl Evictor for LruEvictor {
         self.evict_to_min_size(cache).await
     }
 }
-
-/// [`EvictCandidate`] implementation for the First-in-first-out eviction algorithm.
-#[derive(Debug)]
-struct FifoEc {
-    key: Vec<u8>,
-    last_modified: u64,
-}
-impl EvictCandidate for FifoEc {
-    fn from_meta(key: Vec<u8>, meta: Metadata) -> Self {
-        Self {
-            key,
-            last_modified: meta.get_last_modified_raw(),
-        }
-    }
-    fn key(&self) -> &[u8] {
-        &self.key
-    }
-    fn should_evict_over(&self, other: &Self) -> cmp::Ordering {
-        // `other > self` would result in cmp::Ordering::Greater
-        other.last_modified.cmp(&self.last_modified)
+impl sealed::Sealed for LruEvictor {
+    fn evict_boxed<'a>(&'a self, cache: &'a Cache) -> sealed::EvictFuture<'a> {
+        Box::pin(self.evict_to_min_size(cache))
     }
 }
+impl EmergencyEvictor for LruEvictor {}
 
 /// First-in-first-out eviction algorithm for a [`Cache`]
 ///
@@ -319,8 +243,9 @@ impl EvictCandidate for FifoEc {
 ///
 /// ## O(?) & Async
 ///
-/// This eviction algorithm has no guarantee on being fast or efficient at all. It is expected that
-/// this algorithm is called infrequently, only when absolutely needed.
+/// Candidates are read in order from a secondary index of the metadata database, so finding the
+/// next `batch` candidates is roughly `O(log n + batch)`. The total size of the cache is read from
+/// a counter, so no pass over the whole metadata is required.
 ///
 /// This algorithm also contains blocking calls in an `async` context, mainly metadata iterations
 /// and lookups. The reason for the `async` context is for the actual removals from cache.
@@ -334,9 +259,11 @@ impl EvictCandidate for FifoEc {
 ///
 /// **Batch Size**
 ///
-/// To create one batch, an entire iteration over the metadata must be performed. However, smaller
-/// values means that there is less checking/sorting for each batch found. Higher values should be
-/// used if you're expecting to evict more items at a time.
+/// The number of candidates read from the index at a time. Higher values should be used if you're
+/// expecting to evict more items at a time.
+///
+/// Entries locked by a [`KeyGuard`](crate::KeyGuard) are skipped. If a whole batch of candidates
+/// is locked, eviction stops early.
 ///
 /// # Examples
 ///
@@ -385,7 +312,7 @@ impl FifoEvictor {
     }
 }
 impl MinSzEvictor for FifoEvictor {
-    type Candidate = FifoEc;
+    const INDEX: TimeIndex = TimeIndex::LastModified;
 
     #[inline]
     fn min_size(&self) -> u64 {
@@ -402,3 +329,9 @@ impl Evictor for FifoEvictor {
         self.evict_to_min_size(cache).await
     }
 }
+impl sealed::Sealed for FifoEvictor {
+    fn evict_boxed<'a>(&'a self, cache: &'a Cache) -> sealed::EvictFuture<'a> {
+        Box::pin(self.evict_to_min_size(cache))
+    }
+}
+impl EmergencyEvictor for FifoEvictor {}
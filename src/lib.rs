@@ -14,6 +14,12 @@
 //! - Tuned for large-file databases
 //! - Included cache eviction (LRU/FIFO)
 //! - Easily accessible value metadata
+//! - Configurable integrity hash algorithms (md5/xxh3/blake3/sha256)
+//! - User-defined metadata attributes
+//! - Tag-based grouping and invalidation
+//! - Metadata queries
+//! - Atomic multi-key transactions
+//! - Background integrity scrubbing
 //! - Optimized for cache `HIT`s
 //! - Easy error handling
 //! - `bytes` crate support (non-optional)
@@ -22,7 +28,8 @@
 //!
 //! This database solution easily separates data into two databases: the LFS (large-file-storage)
 //! database, and the metadata database. The LFS database is powered using Tokio's async filesystem
-//! operations, whereas the metadata database is powered using [`sled`].
+//! operations, whereas the metadata database is powered using [`sled`] by default. Other metadata
+//! backends can be selected (or implemented) through the [`backends`] module.
 //!
 //! The advantage of splitting these two up is simple: Accessing metadata (for things like database
 //! eviction) is realatively cheap and efficient, with the only downside being that `async` is not
@@ -66,10 +73,38 @@ pub enum ForcepError {
     MetaSer(bson::error::Error),
     /// Error with metadata sled database operation
     MetaDb(sled::Error),
+    /// Error with an operation of a non-sled [`MetaBackend`](backends::MetaBackend)
+    Backend(Box<dyn error::Error + Send + Sync>),
     /// The entry was found successfully, but the metadata was strangely not present
     MetaNotFound,
     /// The entry for the specified key is not found
     NotFound,
+    /// The metadata database has a format version that this version of `forceps` doesn't
+    /// support (most likely it was created by a newer version), or the version record is
+    /// corrupted (`None`)
+    MetaVersion(Option<u32>),
+    /// The configured integrity [`HashAlgorithm`] requires a cargo feature that isn't enabled
+    HashUnavailable(HashAlgorithm),
+    /// A conditional write (see [`WriteOptions::if_version`]) found a different version of the
+    /// entry than expected
+    VersionMismatch {
+        /// The version the write expected (`0` meaning the entry shouldn't exist)
+        expected: u64,
+        /// The current version of the entry, or `None` if it doesn't exist
+        actual: Option<u64>,
+    },
+    /// The cache directory is locked by another [`Cache`], see [`LockMode`]
+    Locked {
+        /// The PID of the process holding the lock, if it could be determined
+        pid: Option<u32>,
+    },
+    /// The operation would change a cache that was opened in read-only mode, see
+    /// [`CacheBuilder::read_only`]
+    ReadOnly,
+    /// A write failed because the disk (or the quota of the user) is full, even after running
+    /// the emergency evictor if one is configured. See
+    /// [`CacheBuilder::emergency_evictor`].
+    StorageFull(io::Error),
 }
 /// Re-export of [`ForcepError`]
 pub type Error = ForcepError;
@@ -83,11 +118,45 @@ impl std::fmt::Display for ForcepError {
             Self::MetaDe(e) => write!(fmt, "there was a problem deserializing metadata: {e}"),
             Self::MetaSer(e) => write!(fmt, "there was a problem serializing metadata: {e}"),
             Self::MetaDb(e) => write!(fmt, "an error with the metadata database occurred: {e}"),
+            Self::Backend(e) => write!(fmt, "an error with the metadata backend occurred: {e}"),
             Self::MetaNotFound => write!(
                 fmt,
                 "the entry for the key provided was found, but the metadata was strangely not present"
             ),
             Self::NotFound => write!(fmt, "the entry for the key provided was not found"),
+            Self::MetaVersion(Some(v)) => write!(
+                fmt,
+                "the metadata database has an unsupported format version: {v}"
+            ),
+            Self::MetaVersion(None) => {
+                write!(
+                    fmt,
+                    "the metadata database format version record is corrupted"
+                )
+            }
+            Self::HashUnavailable(alg) => write!(
+                fmt,
+                "the {alg:?} integrity algorithm requires a cargo feature that isn't enabled"
+            ),
+            Self::VersionMismatch { expected, actual } => match actual {
+                Some(actual) => write!(
+                    fmt,
+                    "expected version {expected} of the entry, but found version {actual}"
+                ),
+                None => write!(
+                    fmt,
+                    "expected version {expected} of the entry, but the entry doesn't exist"
+                ),
+            },
+            Self::Locked { pid: Some(pid) } => write!(
+                fmt,
+                "the cache directory is locked by another process (pid {pid})"
+            ),
+            Self::Locked { pid: None } => {
+                write!(fmt, "the cache directory is locked by another process")
+            }
+            Self::ReadOnly => write!(fmt, "the cache was opened in read-only mode"),
+            Self::StorageFull(e) => write!(fmt, "there is not enough space left on disk: {e}"),
         }
     }
 }
@@ -98,8 +167,15 @@ impl error::Error for ForcepError {
             Self::MetaDe(e) => Some(e),
             Self::MetaSer(e) => Some(e),
             Self::MetaDb(e) => Some(e),
+            Self::Backend(e) => Some(e.as_ref()),
             Self::MetaNotFound => None,
             Self::NotFound => None,
+            Self::MetaVersion(_) => None,
+            Self::HashUnavailable(_) => None,
+            Self::VersionMismatch { .. } => None,
+            Self::Locked { .. } => None,
+            Self::ReadOnly => None,
+            Self::StorageFull(e) => Some(e),
         }
     }
 }
@@ -108,11 +184,15 @@ mod mem_cache;
 mod tmp;
 
 mod cache;
-pub use cache::{Cache, CacheBuilder};
+pub use cache::{
+    Cache, CacheBuilder, CheckOptions, CheckReport, Durability, KeyGuard, LockMode,
+    QuarantinedEntry, Query, QuerySort, ScrubAction, ScrubOptions, ScrubStatus, StartupReport,
+    Transaction, WriteOptions,
+};
 
 mod metadata;
-pub(crate) use metadata::MetaDb;
-pub use metadata::{Md5Bytes, Metadata};
+pub use metadata::{AttributeValue, Attributes, HashAlgorithm, Md5Bytes, Metadata};
+pub(crate) use metadata::{Intent, MetaDb, PendingAccess, TimeIndex, TxChange, TxOp};
 
 /// A collection of [`Cache`] eviction algorithms and generics
 ///
@@ -121,3 +201,12 @@ pub use metadata::{Md5Bytes, Metadata};
 ///
 /// [`Evictor`]: crate::evictors::Evictor
 pub mod evictors;
+
+/// Storage backends for the metadata database of a [`Cache`]
+///
+/// This module contains the [`MetaBackend`] trait, which abstracts over the key-value store that
+/// [`Metadata`] is persisted in, as well as the implementations that come with `forceps`. The
+/// backend of a [`Cache`] is selected with [`CacheBuilder::meta_backend`].
+///
+/// [`MetaBackend`]: crate::backends::MetaBackend
+pub mod backends;
@@ -55,6 +55,15 @@ impl MemCacheInner {
         other
     }
 
+    fn remove(&self, k: &[u8]) -> Option<Bytes> {
+        let mut guard = self.cache.lock();
+        let removed = guard.pop(&hash_key(k));
+        if let Some(ref removed) = removed {
+            self.current.fetch_sub(removed.len(), Ordering::SeqCst);
+        }
+        removed
+    }
+
     fn evict(&self, lru: &mut Lru, mut current: usize) -> usize {
         // pop items until it meets size requirement
         loop {
@@ -73,7 +82,7 @@ impl MemCacheInner {
     #[cfg(test)]
     fn peek(&self, k: &[u8]) -> Option<Bytes> {
         let guard = self.cache.lock();
-        guard.peek(&hash_key(k)).map(Bytes::clone)
+        guard.peek(&hash_key(k)).cloned()
     }
 }
 
@@ -99,6 +108,10 @@ impl MemCache {
     pub(crate) fn put(&self, k: &[u8], v: Bytes) -> Option<Bytes> {
         self.0.as_ref().and_then(|c| c.put(k, v))
     }
+    #[inline]
+    pub(crate) fn remove(&self, k: &[u8]) -> Option<Bytes> {
+        self.0.as_ref().and_then(|c| c.remove(k))
+    }
 
     // functions for tests
     #[cfg(test)]
@@ -114,7 +127,7 @@ impl MemCache {
 #[cfg(test)]
 mod test {
     use super::*;
-    const D: &'static [u8] = &[0; 4096];
+    const D: &[u8] = &[0; 4096];
 
     #[test]
     fn verify_eviction() {
@@ -141,4 +154,19 @@ mod test {
         cache.put(b"ENT2", Bytes::from(D));
         assert_eq!(cache.current_size().unwrap(), D.len() * 2);
     }
+
+    #[test]
+    fn removal() {
+        let cache = MemCache::new(D.len() * 2);
+        cache.put(b"ENT1", Bytes::from(D));
+        cache.put(b"ENT2", Bytes::from(D));
+        assert!(cache.remove(b"ENT1").is_some());
+        assert!(cache.peek(b"ENT1").is_none());
+        assert_eq!(cache.current_size().unwrap(), D.len());
+
+        // removing a missing entry changes nothing
+        assert!(cache.remove(b"ENT1").is_none());
+        assert_eq!(cache.current_size().unwrap(), D.len());
+        assert!(MemCache::new(0).remove(b"ENT2").is_none());
+    }
 }
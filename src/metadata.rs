@@ -1,14 +1,126 @@
+mod hash;
+mod journal;
+mod migrations;
+
+pub use hash::HashAlgorithm;
+pub(crate) use journal::{Intent, TxChange};
+
+use crate::backends::{Batch, Keyspace, MetaBackend};
 use crate::{ForcepError, Result};
-use std::path;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 use std::time;
 
 /// Type definition for an array of bytes that make up an `md5` hash.
 pub type Md5Bytes = [u8; 16];
 
+/// A single user-defined attribute value that can be attached to an entry's [`Metadata`].
+///
+/// Each variant maps directly to a BSON type, so attributes are persisted alongside the rest of
+/// the entry metadata without any additional conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AttributeValue {
+    /// A UTF-8 string value (BSON `string`)
+    String(String),
+    /// A 64-bit signed integer value (BSON `int64`)
+    Int(i64),
+    /// A boolean value (BSON `bool`)
+    Bool(bool),
+    /// Arbitrary binary data (BSON generic `binary`)
+    Binary(Vec<u8>),
+}
+
+/// Map of user-defined attribute names to their values, stored in an entry's [`Metadata`].
+///
+/// See [`Metadata::get_attributes`] and [`Cache::update_attributes`](crate::Cache::update_attributes).
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+impl From<String> for AttributeValue {
+    #[inline]
+    fn from(v: String) -> Self {
+        Self::String(v)
+    }
+}
+impl From<&str> for AttributeValue {
+    #[inline]
+    fn from(v: &str) -> Self {
+        Self::String(v.to_owned())
+    }
+}
+impl From<i64> for AttributeValue {
+    #[inline]
+    fn from(v: i64) -> Self {
+        Self::Int(v)
+    }
+}
+impl From<i32> for AttributeValue {
+    #[inline]
+    fn from(v: i32) -> Self {
+        Self::Int(v as i64)
+    }
+}
+impl From<bool> for AttributeValue {
+    #[inline]
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+impl From<Vec<u8>> for AttributeValue {
+    #[inline]
+    fn from(v: Vec<u8>) -> Self {
+        Self::Binary(v)
+    }
+}
+impl From<&[u8]> for AttributeValue {
+    #[inline]
+    fn from(v: &[u8]) -> Self {
+        Self::Binary(v.to_vec())
+    }
+}
+
+impl AttributeValue {
+    /// Returns the inner string if this value is an [`AttributeValue::String`]
+    #[inline]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    /// Returns the inner integer if this value is an [`AttributeValue::Int`]
+    #[inline]
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+    /// Returns the inner boolean if this value is an [`AttributeValue::Bool`]
+    #[inline]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+    /// Returns the inner bytes if this value is an [`AttributeValue::Binary`]
+    #[inline]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Binary(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
 /// Metadata information about a certain entry in the cache
 ///
 /// This metadata contains information about when the entry was last modified, the size (in bytes)
-/// of the entry, the `md5` integrity of the entry, etc.
+/// of the entry, the integrity hash of the entry, etc.
 ///
 /// # Examples
 ///
@@ -27,7 +139,13 @@ pub type Md5Bytes = [u8; 16];
 /// let metadata = cache.read_metadata(&b"MY_KEY").unwrap();
 /// # }
 /// ```
-#[derive(Debug)]
+///
+/// # Serde
+///
+/// With the `serde` feature enabled, [`Metadata`] implements `Serialize` and `Deserialize`. The
+/// integrity hash is (de)serialized as a lowercase hex string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metadata {
     /// Size in bytes of the corresponding entry
     size: u64,
@@ -37,14 +155,165 @@ pub struct Metadata {
     last_accessed: u64,
     /// Number of times this entry has been HIT (total accesses)
     hits: u64,
-    /// Md5 hash of the underlying data
-    integrity: Md5Bytes,
+    /// First time this entry was written, milliseconds since epoch
+    created_at: u64,
+    /// Number of times this entry has been written, starting at `1`
+    version: u64,
+    /// Hash of the underlying data
+    #[cfg_attr(feature = "serde", serde(with = "hex_bytes"))]
+    integrity: Vec<u8>,
+    /// The algorithm that `integrity` was computed with
+    integrity_alg: HashAlgorithm,
+    /// The persisted identifier of the algorithm if it's [`HashAlgorithm::Unknown`], so it's
+    /// written back unchanged
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unknown_alg: Option<String>,
+    /// User-defined attributes attached to the entry
+    attributes: Attributes,
+    /// Sorted list of tags the entry belongs to
+    tags: Vec<String>,
+}
+
+/// (De)serializes bytes as a lowercase hex string
+#[cfg(feature = "serde")]
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s).map_err(D::Error::custom)
+    }
+}
+
+/// Access tracking updates of a single entry that haven't been written to the database yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PendingAccess {
+    /// Number of accesses to add to the `hits` counter
+    pub hits: u64,
+    /// Time of the newest access, milliseconds since epoch
+    pub last_accessed: u64,
+}
+
+impl PendingAccess {
+    /// A single access that happened just now
+    #[inline]
+    pub fn now() -> Self {
+        Self {
+            hits: 1,
+            last_accessed: now_since_epoch(),
+        }
+    }
+
+    /// Merges `other` into `self`, adding up the hits and keeping the newest access time
+    #[inline]
+    pub fn merge(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.last_accessed = self.last_accessed.max(other.last_accessed);
+    }
+}
+
+/// A single operation of a metadata transaction, see [`MetaDb::apply_transaction`].
+#[derive(Debug)]
+pub(crate) enum TxOp {
+    /// Inserts `meta` for the entry `key`, replacing any previous metadata
+    Insert {
+        key: Vec<u8>,
+        meta: Metadata,
+        /// Version the entry must have, `0` meaning it must not exist
+        expected_version: Option<u64>,
+    },
+    /// Removes the metadata of the entry `key`, which must exist
+    Remove { key: Vec<u8> },
+}
+
+impl TxOp {
+    /// The key of the entry this operation changes
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Insert { key, .. } | Self::Remove { key } => key,
+        }
+    }
 }
 
 /// Database for cache entry metadata
+///
+/// This sits on top of a [`MetaBackend`] and is responsible for (de)serializing metadata and
+/// keeping the secondary indexes (such as the tag index, which maps `tag ++ TAG_SEP ++ key`) in
+/// sync with the entries.
 #[derive(Debug)]
 pub(crate) struct MetaDb {
-    db: sled::Db,
+    backend: Arc<dyn MetaBackend>,
+    /// The algorithm used to compute the integrity of new entries
+    hash_alg: HashAlgorithm,
+    /// Serializes every read-modify-write of the database, so a batch is always computed from
+    /// the latest state of the entries it touches
+    write_lock: Mutex<()>,
+    /// The id of the next intent recorded in the journal, see the `journal` module
+    next_intent: AtomicU64,
+}
+
+/// Key in the `info` keyspace that holds the format version of the database
+const VERSION_KEY: &[u8] = b"version";
+
+/// Key in the `info` keyspace that holds the total size (in bytes) of every entry
+const TOTAL_SIZE_KEY: &[u8] = b"total_size";
+
+/// Key in the `info` keyspace that holds the total number of entries
+const ENTRY_COUNT_KEY: &[u8] = b"entry_count";
+
+/// Separator between the tag and key in the tag index. `0xFF` never appears in UTF-8, so a tag
+/// can never contain it.
+const TAG_SEP: u8 = 0xFF;
+
+/// The prefix that every tag index key for `tag` starts with
+fn tag_prefix(tag: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(tag.len() + 1);
+    buf.extend_from_slice(tag.as_bytes());
+    buf.push(TAG_SEP);
+    buf
+}
+
+/// The tag index key for an entry with the key `key` tagged with `tag`
+fn tag_index_key(tag: &str, key: &[u8]) -> Vec<u8> {
+    let mut buf = tag_prefix(tag);
+    buf.extend_from_slice(key);
+    buf
+}
+
+/// A secondary index that orders entries by one of their timestamps, oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimeIndex {
+    /// Ordered by [`Metadata::get_last_accessed_raw`]
+    LastAccessed,
+    /// Ordered by [`Metadata::get_last_modified_raw`]
+    LastModified,
+}
+
+impl TimeIndex {
+    /// The keyspace this index is stored in
+    #[inline]
+    fn keyspace(self) -> Keyspace {
+        match self {
+            Self::LastAccessed => Keyspace::AccessTime,
+            Self::LastModified => Keyspace::ModifiedTime,
+        }
+    }
+}
+
+/// Length of the big-endian timestamp every time index key starts with
+const TIME_PREFIX_LEN: usize = 8;
+
+/// The time index key for an entry with the key `key` and the timestamp `ts`. Timestamps are
+/// big-endian so the keys sort by time, and the entry key keeps them unique.
+fn time_index_key(ts: u64, key: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(TIME_PREFIX_LEN + key.len());
+    buf.extend_from_slice(&ts.to_be_bytes());
+    buf.extend_from_slice(key);
+    buf
 }
 
 /// Milliseconds from epoch to now
@@ -56,22 +325,66 @@ fn now_since_epoch() -> u64 {
 }
 
 impl Metadata {
-    /// Creates a new instance of [`Metadata`] from the given `data`
-    pub(crate) fn new(data: &[u8]) -> Self {
+    /// Creates a new instance of [`Metadata`] from the given `data`, user `attributes`, and
+    /// `tags`, hashing the data with `hash_alg`.
+    ///
+    /// The entry is marked as modified and accessed just now. This is mostly useful for testing
+    /// code that consumes [`Metadata`] without going through a [`Cache`](crate::Cache). If
+    /// `hash_alg` [is not available](HashAlgorithm::is_available), the integrity is left empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use forceps::{Attributes, HashAlgorithm, Metadata};
+    ///
+    /// let meta = Metadata::new(b"Hello World", HashAlgorithm::Md5, Attributes::new(), vec![]);
+    /// assert_eq!(meta.get_size(), 11);
+    /// assert!(meta.check_integrity_of(b"Hello World"));
+    /// ```
+    pub fn new(
+        data: &[u8],
+        hash_alg: HashAlgorithm,
+        attributes: Attributes,
+        tags: Vec<String>,
+    ) -> Self {
+        // availability of the algorithm is checked when the database is opened
+        let integrity = hash_alg.digest(data).unwrap_or_default();
+        Self::with_digest(data.len() as u64, integrity, hash_alg, attributes, tags)
+    }
+
+    /// Creates the metadata of a new entry of `size` bytes like [`new`](Self::new), using an
+    /// `integrity` digest that was already computed with `hash_alg`.
+    pub(crate) fn with_digest(
+        size: u64,
+        integrity: Vec<u8>,
+        hash_alg: HashAlgorithm,
+        attributes: Attributes,
+        mut tags: Vec<String>,
+    ) -> Self {
+        tags.sort_unstable();
+        tags.dedup();
+        let now = now_since_epoch();
         Self {
-            size: data.len() as u64,
-            last_modified: now_since_epoch(),
-            last_accessed: now_since_epoch(),
+            size,
+            last_modified: now,
+            last_accessed: now,
             hits: 0,
-            integrity: md5::compute(data).into(),
+            created_at: now,
+            version: 1,
+            integrity,
+            integrity_alg: hash_alg,
+            unknown_alg: None,
+            attributes,
+            tags,
         }
     }
 
     /// Serializes the metadata into bytes
-    pub(crate) fn serialize(&self) -> Vec<u8> {
+    pub(crate) fn serialize(&self) -> Result<Vec<u8>> {
         use bson::{
             cstr,
-            raw::{RawBinaryRef, RawBson, RawDocumentBuf},
+            raw::{CStr, RawArrayBuf, RawBinaryRef, RawBson, RawDocumentBuf},
+            spec::BinarySubtype,
         };
 
         let mut doc = RawDocumentBuf::new();
@@ -85,19 +398,59 @@ impl Metadata {
             RawBson::Int64(self.last_accessed as i64),
         );
         doc.append(cstr!("hits"), RawBson::Int64(self.hits as i64));
+        doc.append(cstr!("created_at"), RawBson::Int64(self.created_at as i64));
+        doc.append(cstr!("version"), RawBson::Int64(self.version as i64));
+        // md5 keeps its dedicated binary subtype so records stay readable by older versions
+        let subtype = match self.integrity_alg {
+            HashAlgorithm::Md5 => BinarySubtype::Md5,
+            _ => BinarySubtype::Generic,
+        };
         doc.append(
             cstr!("integrity"),
             RawBinaryRef {
-                subtype: bson::spec::BinarySubtype::Md5,
+                subtype,
                 bytes: &self.integrity,
             },
         );
-        doc.into_bytes()
+        let alg_id = self.unknown_alg.as_deref();
+        doc.append(
+            cstr!("integrity_alg"),
+            alg_id.unwrap_or(self.integrity_alg.id()),
+        );
+
+        let mut attrs = RawDocumentBuf::new();
+        for (name, value) in &self.attributes {
+            let name = <&CStr>::try_from(name.as_str()).map_err(ForcepError::MetaSer)?;
+            match value {
+                AttributeValue::String(v) => attrs.append(name, v.as_str()),
+                AttributeValue::Int(v) => attrs.append(name, *v),
+                AttributeValue::Bool(v) => attrs.append(name, *v),
+                AttributeValue::Binary(v) => attrs.append(
+                    name,
+                    RawBinaryRef {
+                        subtype: BinarySubtype::Generic,
+                        bytes: v,
+                    },
+                ),
+            }
+        }
+        doc.append(cstr!("attributes"), attrs);
+
+        let mut tags = RawArrayBuf::new();
+        for tag in &self.tags {
+            tags.push(tag.as_str());
+        }
+        doc.append(cstr!("tags"), tags);
+        Ok(doc.into_bytes())
     }
 
     /// Deserializes a slice of bytes into metadata
     pub(crate) fn deserialize(buf: &[u8]) -> Result<Self> {
-        use bson::{error::Error as BsonError, raw::RawDocument, spec::BinarySubtype};
+        use bson::{
+            error::Error as BsonError,
+            raw::{RawBsonRef, RawDocument},
+            spec::BinarySubtype,
+        };
 
         let doc = RawDocument::from_bytes(buf).map_err(ForcepError::MetaDe)?;
 
@@ -108,37 +461,119 @@ impl Metadata {
             ForcepError::MetaDe(err)
         };
 
-        let read_u64 = |key: &str| -> Result<u64> {
-            doc.get_i64(key)
-                .map(|v| v as u64)
-                .map_err(ForcepError::MetaDe)
+        // only `size` and `integrity` are required, every other field falls back to a default
+        // when missing so records written by other format versions can still be read. unknown
+        // fields are ignored entirely.
+        let read_u64_or = |key: &str, default: Option<u64>| -> Result<u64> {
+            match doc.get(key).map_err(ForcepError::MetaDe)? {
+                Some(RawBsonRef::Int64(v)) => Ok(v as u64),
+                Some(RawBsonRef::Int32(v)) => Ok(v as u64),
+                Some(_) => Err(make_error(key, "expected integer")),
+                None => default.ok_or_else(|| make_error(key, "missing required field")),
+            }
         };
 
-        let size = read_u64("size")?;
-        let last_modified = read_u64("last_modified")?;
-        let last_accessed = read_u64("last_accessed")?;
-        let hits = read_u64("hits")?;
+        let size = read_u64_or("size", None)?;
+        let last_modified = read_u64_or("last_modified", Some(0))?;
+        let last_accessed = read_u64_or("last_accessed", Some(last_modified))?;
+        let hits = read_u64_or("hits", Some(0))?;
+        // the creation time wasn't tracked before, so the best guess is the last write
+        let created_at = read_u64_or("created_at", Some(last_modified))?;
+        let version = read_u64_or("version", Some(1))?;
 
+        // records written before the algorithm was configurable are always md5. algorithms of
+        // newer versions only fail verification, so the rest of the record stays usable.
+        let (integrity_alg, unknown_alg) =
+            match doc.get("integrity_alg").map_err(ForcepError::MetaDe)? {
+                Some(RawBsonRef::String(id)) => match HashAlgorithm::from_id(id) {
+                    Some(alg) => (alg, None),
+                    None => (HashAlgorithm::Unknown, Some(id.to_owned())),
+                },
+                Some(_) => return Err(make_error("integrity_alg", "expected string")),
+                None => (HashAlgorithm::Md5, None),
+            };
         let binary = doc.get_binary("integrity").map_err(ForcepError::MetaDe)?;
-        if binary.subtype != BinarySubtype::Md5 {
-            return Err(make_error("integrity", "expected MD5 binary subtype"));
+        if integrity_alg == HashAlgorithm::Md5 {
+            if binary.subtype != BinarySubtype::Md5 {
+                return Err(make_error("integrity", "expected MD5 binary subtype"));
+            }
+            const MD5_LEN: usize = 16;
+            if binary.bytes.len() != MD5_LEN {
+                return Err(make_error("integrity", "integrity must contain 16 bytes"));
+            }
         }
-        const MD5_LEN: usize = 16;
-        if binary.bytes.len() != MD5_LEN {
-            return Err(make_error("integrity", "integrity must contain 16 bytes"));
+        let integrity = binary.bytes.to_vec();
+
+        // entries written before attributes were introduced won't have the field at all
+        let mut attributes = Attributes::new();
+        if let Some(attrs) = doc.get("attributes").map_err(ForcepError::MetaDe)? {
+            let attrs = match attrs {
+                RawBsonRef::Document(attrs) => attrs,
+                _ => return Err(make_error("attributes", "expected embedded document")),
+            };
+            for result in attrs {
+                let (name, value) = result.map_err(ForcepError::MetaDe)?;
+                let value = match value {
+                    RawBsonRef::String(v) => AttributeValue::String(v.to_owned()),
+                    RawBsonRef::Int64(v) => AttributeValue::Int(v),
+                    RawBsonRef::Int32(v) => AttributeValue::Int(v as i64),
+                    RawBsonRef::Boolean(v) => AttributeValue::Bool(v),
+                    RawBsonRef::Binary(v) => AttributeValue::Binary(v.bytes.to_vec()),
+                    // attribute types from newer versions are skipped
+                    _ => continue,
+                };
+                attributes.insert(name.as_str().to_owned(), value);
+            }
+        }
+
+        // same goes for tags
+        let mut tags = Vec::new();
+        if let Some(arr) = doc.get("tags").map_err(ForcepError::MetaDe)? {
+            let arr = match arr {
+                RawBsonRef::Array(arr) => arr,
+                _ => return Err(make_error("tags", "expected array")),
+            };
+            for result in arr {
+                match result.map_err(ForcepError::MetaDe)? {
+                    RawBsonRef::String(tag) => tags.push(tag.to_owned()),
+                    _ => return Err(make_error("tags", "tags must be strings")),
+                }
+            }
         }
-        let mut integrity = [0u8; MD5_LEN];
-        integrity.copy_from_slice(binary.bytes);
 
         Ok(Self {
             size,
             last_modified,
             last_accessed,
             hits,
+            created_at,
+            version,
             integrity,
+            integrity_alg,
+            unknown_alg,
+            attributes,
+            tags,
         })
     }
 
+    /// Sets every timestamp of the metadata to `millis` since epoch, used when the real times of
+    /// an entry are unknown.
+    #[inline]
+    pub(crate) fn with_times(mut self, millis: u64) -> Self {
+        self.last_modified = millis;
+        self.last_accessed = millis;
+        self.created_at = millis;
+        self
+    }
+
+    /// Applies an access tracking update to the metadata, adding up the hits and keeping the
+    /// newest access time.
+    #[inline]
+    pub(crate) fn apply_access(&mut self, access: PendingAccess) {
+        self.hits += access.hits;
+        self.last_accessed = self.last_accessed.max(access.last_accessed);
+    }
+
     /// The size in bytes of the corresponding cache entry.
     #[inline]
     pub fn get_size(&self) -> u64 {
@@ -161,6 +596,37 @@ impl Metadata {
         self.last_modified
     }
 
+    /// Retrieves the first time this entry was written. Unlike
+    /// [`get_last_modified`](Self::get_last_modified), this is preserved when the entry is
+    /// overwritten.
+    ///
+    /// **NOTE:** For entries written by older versions of `forceps`, this is the same as the last
+    /// modified time.
+    #[inline]
+    pub fn get_created_at(&self) -> Option<time::SystemTime> {
+        match self.created_at {
+            0 => None,
+            millis => Some(time::UNIX_EPOCH + time::Duration::from_millis(millis)),
+        }
+    }
+    /// Retrieves the raw `created_at` time, which is the milliseconds since
+    /// [`time::UNIX_EPOCH`]. If the returned result is `0`, that means there is no `created_at`
+    /// time.
+    #[inline]
+    pub fn get_created_at_raw(&self) -> u64 {
+        self.created_at
+    }
+
+    /// The write version of this entry, which starts at `1` and increases by one every time the
+    /// entry is overwritten.
+    ///
+    /// This can be used for optimistic concurrency with
+    /// [`WriteOptions::if_version`](crate::WriteOptions::if_version).
+    #[inline]
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
     /// The total number of times this entry has been read.
     ///
     /// **NOTE:** This will be 0 unless `track_access` is enabled from the [`CacheBuilder`]
@@ -200,84 +666,539 @@ impl Metadata {
     }
 
     /// Retrieves the internal [`Md5Bytes`] integrity of the corresponding metadata entry.
+    ///
+    /// Entries whose integrity wasn't computed with [`HashAlgorithm::Md5`] return all zeroes.
+    #[deprecated(
+        note = "entries can use other hash algorithms, for which this returns all zeroes. use \
+                `get_integrity_digest` instead"
+    )]
     #[inline]
     pub fn get_integrity(&self) -> &Md5Bytes {
+        const NONE: &Md5Bytes = &[0; 16];
+        match self.integrity_alg {
+            HashAlgorithm::Md5 => self.integrity.as_slice().try_into().unwrap_or(NONE),
+            _ => NONE,
+        }
+    }
+    /// Retrieves the raw integrity hash of the corresponding metadata entry. The algorithm it was
+    /// computed with can be found with [`get_integrity_algorithm`](Self::get_integrity_algorithm).
+    #[inline]
+    pub fn get_integrity_digest(&self) -> &[u8] {
         &self.integrity
     }
+    /// Retrieves the [`HashAlgorithm`] that the integrity of this entry was computed with, which
+    /// is [`HashAlgorithm::Unknown`] for algorithms of newer versions.
+    #[inline]
+    pub fn get_integrity_algorithm(&self) -> HashAlgorithm {
+        self.integrity_alg
+    }
 
     /// Verifies that the metadata integrity matches the integrity of the data provided.
+    ///
+    /// The data is hashed with the same algorithm that the entry was written with. If support for
+    /// that algorithm isn't compiled in (see [`HashAlgorithm::is_available`]), this will always
+    /// return `false`.
     #[inline]
     pub fn check_integrity_of(&self, data: &[u8]) -> bool {
-        let other_integrity: Md5Bytes = md5::compute(data).into();
-        other_integrity == self.integrity
+        self.integrity_alg
+            .digest(data)
+            .is_some_and(|other| other == self.integrity)
+    }
+
+    /// Retrieves all of the user-defined attributes attached to this entry.
+    ///
+    /// Attributes can be set when writing using [`WriteOptions::attribute`] and changed later
+    /// with [`Cache::update_attributes`].
+    ///
+    /// [`WriteOptions::attribute`]: crate::WriteOptions::attribute
+    /// [`Cache::update_attributes`]: crate::Cache::update_attributes
+    #[inline]
+    pub fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    /// Retrieves a single user-defined attribute by `name`, if it is present.
+    #[inline]
+    pub fn get_attribute(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes.get(name)
+    }
+
+    /// Retrieves the sorted list of tags this entry belongs to.
+    ///
+    /// Tags can be set when writing using [`WriteOptions::tag`].
+    ///
+    /// [`WriteOptions::tag`]: crate::WriteOptions::tag
+    #[inline]
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+    /// Whether this entry has been tagged with `tag`.
+    #[inline]
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.binary_search_by(|t| t.as_str().cmp(tag)).is_ok()
     }
 }
 
 impl MetaDb {
-    /// Initializes a new metadata database with sled.
-    pub fn new(path: &path::Path) -> Result<Self> {
-        sled::open(path)
-            .map_err(ForcepError::MetaDb)
-            .map(|db| Self { db })
+    /// Initializes a new metadata database on top of `backend`, computing the integrity of new
+    /// entries with `hash_alg`.
+    pub fn new(backend: Arc<dyn MetaBackend>, hash_alg: HashAlgorithm) -> Result<Self> {
+        if !hash_alg.is_available() {
+            return Err(ForcepError::HashUnavailable(hash_alg));
+        }
+        Ok(Self {
+            next_intent: AtomicU64::new(journal::next_id(backend.as_ref())?),
+            backend,
+            hash_alg,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Retrieves the format version of the database.
+    ///
+    /// Databases created before the version record was introduced have no version, in which case
+    /// `None` is returned.
+    pub fn format_version(&self) -> Result<Option<u32>> {
+        let bytes = match self.backend.get(Keyspace::Info, VERSION_KEY)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let bytes: [u8; 4] = bytes[..]
+            .try_into()
+            .map_err(|_| ForcepError::MetaVersion(None))?;
+        Ok(Some(u32::from_be_bytes(bytes)))
+    }
+
+    /// Sets the format version record of the database.
+    pub fn set_format_version(&self, version: u32) -> Result<()> {
+        self.backend
+            .insert(Keyspace::Info, VERSION_KEY, &version.to_be_bytes())
+    }
+
+    /// Reads one of the big-endian counters of the `info` keyspace, which is `0` if it doesn't
+    /// exist yet.
+    fn read_counter(&self, name: &[u8]) -> Result<u64> {
+        let bytes = match self.backend.get(Keyspace::Info, name)? {
+            Some(bytes) => bytes,
+            None => return Ok(0),
+        };
+        let bytes: [u8; 8] = bytes[..].try_into().map_err(|_| {
+            let io_err = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "counter must contain 8 bytes",
+            );
+            let mut err = bson::error::Error::from(io_err);
+            err.key = Some(String::from_utf8_lossy(name).into_owned());
+            ForcepError::MetaDe(err)
+        })?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// The total size (in bytes) of every entry in the database.
+    #[inline]
+    pub fn total_size(&self) -> Result<u64> {
+        self.read_counter(TOTAL_SIZE_KEY)
+    }
+
+    /// The total number of entries in the database.
+    #[inline]
+    pub fn entry_count(&self) -> Result<u64> {
+        self.read_counter(ENTRY_COUNT_KEY)
+    }
+
+    /// Overwrites the total size and entry count counters.
+    fn set_totals(&self, size: u64, count: u64) -> Result<()> {
+        let mut batch = Batch::new();
+        batch.insert(Keyspace::Info, TOTAL_SIZE_KEY, size.to_be_bytes());
+        batch.insert(Keyspace::Info, ENTRY_COUNT_KEY, count.to_be_bytes());
+        self.backend.apply_batch(batch)
+    }
+
+    /// Adds the operations that update the total size and entry count counters to `batch`, for an
+    /// operation that adds the entry `added` and removes the entry `removed`.
+    ///
+    /// Must be called while holding the write lock, so the counters can't change until the batch
+    /// has been applied.
+    fn batch_totals(
+        &self,
+        batch: &mut Batch,
+        added: Option<&Metadata>,
+        removed: Option<&Metadata>,
+    ) -> Result<()> {
+        let totals_of = |meta: Option<&Metadata>| meta.map_or((0, 0), |m| (m.size, 1));
+        self.batch_totals_delta(batch, totals_of(added), totals_of(removed))
+    }
+
+    /// Like [`batch_totals`](Self::batch_totals), but for an operation that adds and removes
+    /// any number of entries, each given as a `(size, count)` pair.
+    fn batch_totals_delta(
+        &self,
+        batch: &mut Batch,
+        added: (u64, u64),
+        removed: (u64, u64),
+    ) -> Result<()> {
+        let size = (self.total_size()? + added.0).saturating_sub(removed.0);
+        let count = (self.entry_count()? + added.1).saturating_sub(removed.1);
+        batch.insert(Keyspace::Info, TOTAL_SIZE_KEY, size.to_be_bytes());
+        batch.insert(Keyspace::Info, ENTRY_COUNT_KEY, count.to_be_bytes());
+        Ok(())
+    }
+
+    /// Persists every write to the metadata database, blocking until it is done.
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    /// Upgrades the database to the current format version by running every required migration.
+    ///
+    /// See the `migrations` module for more information.
+    #[inline]
+    pub fn migrate(&self) -> Result<()> {
+        migrations::migrate(self)
+    }
+
+    /// Whether the database is already at the current format version, so it can be used without
+    /// migrating it.
+    #[inline]
+    pub fn is_migrated(&self) -> Result<bool> {
+        migrations::is_current(self)
     }
 
     /// Retrieves an entry in the metadata database with the corresponding key.
     pub fn get_metadata(&self, key: &[u8]) -> Result<Metadata> {
-        let data = match self.db.get(key) {
-            Ok(Some(data)) => data,
-            Ok(None) => return Err(ForcepError::MetaNotFound),
-            Err(e) => return Err(ForcepError::MetaDb(e)),
-        };
-        Metadata::deserialize(&data)
+        match self.backend.get(Keyspace::Entries, key)? {
+            Some(data) => Metadata::deserialize(&data),
+            None => Err(ForcepError::MetaNotFound),
+        }
+    }
+
+    /// Retrieves an entry in the metadata database, returning `None` if it doesn't exist.
+    pub fn get_metadata_opt(&self, key: &[u8]) -> Result<Option<Metadata>> {
+        match self.get_metadata(key) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(ForcepError::MetaNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds the operations that remove `meta` of the entry `key` (and all of its secondary index
+    /// entries) to `batch`.
+    fn batch_remove(batch: &mut Batch, key: &[u8], meta: &Metadata) {
+        batch.remove(Keyspace::Entries, key);
+        Self::batch_remove_indexes(batch, key, meta);
+    }
+
+    /// Adds the operations that remove every secondary index entry of `meta` for the entry `key`
+    /// to `batch`.
+    fn batch_remove_indexes(batch: &mut Batch, key: &[u8], meta: &Metadata) {
+        for tag in &meta.tags {
+            batch.remove(Keyspace::Tags, tag_index_key(tag, key));
+        }
+        batch.remove(
+            Keyspace::AccessTime,
+            time_index_key(meta.last_accessed, key),
+        );
+        batch.remove(
+            Keyspace::ModifiedTime,
+            time_index_key(meta.last_modified, key),
+        );
+    }
+
+    /// Adds the operations that insert the time index entries of `meta` for the entry `key` to
+    /// `batch`.
+    fn batch_insert_times(batch: &mut Batch, key: &[u8], meta: &Metadata) {
+        batch.insert(
+            Keyspace::AccessTime,
+            time_index_key(meta.last_accessed, key),
+            Vec::new(),
+        );
+        batch.insert(
+            Keyspace::ModifiedTime,
+            time_index_key(meta.last_modified, key),
+            Vec::new(),
+        );
+    }
+
+    /// Adds the operations that insert `meta` for the entry `key` (and all of its secondary index
+    /// entries) to `batch`. `prev` is the metadata that is being replaced, if any.
+    fn batch_insert(
+        batch: &mut Batch,
+        key: &[u8],
+        meta: &Metadata,
+        prev: Option<&Metadata>,
+    ) -> Result<()> {
+        if let Some(prev) = prev {
+            Self::batch_remove_indexes(batch, key, prev);
+        }
+        batch.insert(Keyspace::Entries, key, meta.serialize()?);
+        for tag in &meta.tags {
+            batch.insert(Keyspace::Tags, tag_index_key(tag, key), Vec::new());
+        }
+        Self::batch_insert_times(batch, key, meta);
+        Ok(())
+    }
+
+    /// Inserts the time index entries of `meta` for the entry `key`, without touching the entry
+    /// itself. Only used by migrations to build the indexes for existing entries.
+    fn index_times(&self, key: &[u8], meta: &Metadata) -> Result<()> {
+        let mut batch = Batch::new();
+        Self::batch_insert_times(&mut batch, key, meta);
+        self.backend.apply_batch(batch)
+    }
+
+    /// Creates the metadata of a new entry with `data`, computing its integrity with the
+    /// algorithm of this database.
+    #[inline]
+    pub fn new_metadata(&self, data: &[u8], attributes: Attributes, tags: Vec<String>) -> Metadata {
+        Metadata::new(data, self.hash_alg, attributes, tags)
     }
 
     /// Inserts a new entry into the metadata database for the associated key and data.
     ///
-    /// If a previous entry exists, it is simply overwritten.
-    pub fn insert_metadata_for(&self, key: &[u8], data: &[u8]) -> Result<Metadata> {
-        let meta = Metadata::new(data);
-        let bytes = Metadata::serialize(&meta);
-        self.db
-            .insert(key, &bytes[..])
-            .map_err(ForcepError::MetaDb)?;
+    /// If a previous entry exists, it is simply overwritten. The secondary indexes and the total
+    /// size and entry count are updated in the same batch, so tags and timestamps of the previous
+    /// entry are dropped.
+    #[cfg(test)]
+    pub fn insert_metadata_for(
+        &self,
+        key: &[u8],
+        data: &[u8],
+        attributes: Attributes,
+        tags: Vec<String>,
+    ) -> Result<Metadata> {
+        let meta = self.new_metadata(data, attributes, tags);
+        self.insert_metadata_with(key, meta, None)
+    }
+
+    /// Inserts `meta` for the entry `key` like [`insert_metadata_for`](Self::insert_metadata_for),
+    /// but only if the current version of the entry is `expected_version` (`0` meaning the entry
+    /// must not exist). The creation time and version of `meta` are taken over from the previous
+    /// entry, if any.
+    ///
+    pub fn insert_metadata_with(
+        &self,
+        key: &[u8],
+        mut meta: Metadata,
+        expected_version: Option<u64>,
+    ) -> Result<Metadata> {
+        let _guard = self.write_lock.lock();
+        let prev = self.get_metadata_opt(key)?;
+        let actual = prev.as_ref().map(|p| p.version);
+        if let Some(expected) = expected_version
+            && actual.unwrap_or(0) != expected
+        {
+            return Err(ForcepError::VersionMismatch { expected, actual });
+        }
+        if let Some(prev) = &prev {
+            meta.created_at = prev.created_at;
+            meta.version = prev.version + 1;
+        }
+
+        let mut batch = Batch::new();
+        Self::batch_insert(&mut batch, key, &meta, prev.as_ref())?;
+        self.batch_totals(&mut batch, Some(&meta), prev.as_ref())?;
+        self.backend.apply_batch(batch)?;
         Ok(meta)
     }
 
-    pub fn remove_metadata_for(&self, key: &[u8]) -> Result<Metadata> {
-        match self.db.remove(key) {
-            Ok(Some(m)) => Metadata::deserialize(&m[..]),
-            Ok(None) => Err(ForcepError::MetaNotFound),
-            Err(e) => Err(ForcepError::MetaDb(e)),
+    /// Applies every operation in `ops` (in order) in a single atomic batch, returning the
+    /// inserted or removed metadata of each operation.
+    ///
+    /// If any operation can't be applied (a version doesn't match, or an entry to remove doesn't
+    /// exist), nothing is written. The journal intent `intent` is ended by the same batch, so
+    /// it's still in the journal exactly when the metadata wasn't updated.
+    pub fn apply_transaction(&self, ops: Vec<TxOp>, intent: u64) -> Result<Vec<Metadata>> {
+        let _guard = self.write_lock.lock();
+        // the state of every touched entry, including the changes of earlier operations
+        let mut current = HashMap::<Vec<u8>, Option<Metadata>>::new();
+        let mut batch = Batch::new();
+        let (mut added, mut removed) = ((0, 0), (0, 0));
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops {
+            let prev = match current.remove(op.key()) {
+                Some(prev) => prev,
+                None => self.get_metadata_opt(op.key())?,
+            };
+            if let Some(prev) = &prev {
+                removed = (removed.0 + prev.size, removed.1 + 1);
+            }
+            match op {
+                TxOp::Insert {
+                    key,
+                    mut meta,
+                    expected_version,
+                } => {
+                    let actual = prev.as_ref().map(|p| p.version);
+                    if let Some(expected) = expected_version
+                        && actual.unwrap_or(0) != expected
+                    {
+                        return Err(ForcepError::VersionMismatch { expected, actual });
+                    }
+                    if let Some(prev) = &prev {
+                        meta.created_at = prev.created_at;
+                        meta.version = prev.version + 1;
+                    }
+                    Self::batch_insert(&mut batch, &key, &meta, prev.as_ref())?;
+                    added = (added.0 + meta.size, added.1 + 1);
+                    current.insert(key, Some(meta.clone()));
+                    results.push(meta);
+                }
+                TxOp::Remove { key } => {
+                    let prev = prev.ok_or(ForcepError::MetaNotFound)?;
+                    Self::batch_remove(&mut batch, &key, &prev);
+                    current.insert(key, None);
+                    results.push(prev);
+                }
+            }
+        }
+        self.batch_totals_delta(&mut batch, added, removed)?;
+        batch.remove(Keyspace::Journal, intent.to_be_bytes());
+        self.backend.apply_batch(batch)?;
+        Ok(results)
+    }
+
+    /// Inserts `meta` for the entry `key`, but only if the entry doesn't have any metadata yet.
+    ///
+    /// Used to restore the metadata of entries whose files still exist. Returns whether the
+    /// metadata was inserted.
+    pub fn restore_metadata(&self, key: &[u8], meta: &Metadata) -> Result<bool> {
+        let _guard = self.write_lock.lock();
+        if self.get_metadata_opt(key)?.is_some() {
+            return Ok(false);
         }
+        let mut batch = Batch::new();
+        Self::batch_insert(&mut batch, key, meta, None)?;
+        self.batch_totals(&mut batch, Some(meta), None)?;
+        self.backend.apply_batch(batch)?;
+        Ok(true)
+    }
+
+    /// Replaces the user-defined attributes of the entry with the associated key, using `f` to
+    /// modify the existing attributes. No other metadata values are changed.
+    pub fn update_attributes<F>(&self, key: &[u8], f: F) -> Result<Metadata>
+    where
+        F: FnOnce(&mut Attributes),
+    {
+        let _guard = self.write_lock.lock();
+        let mut meta = self.get_metadata(key)?;
+        f(&mut meta.attributes);
+        self.backend
+            .insert(Keyspace::Entries, key, &meta.serialize()?)?;
+        Ok(meta)
+    }
+
+    /// Removes the entry with the associated key from the metadata database, along with any
+    /// secondary index entries that point to it, and updates the total size and entry count.
+    pub fn remove_metadata_for(&self, key: &[u8]) -> Result<Metadata> {
+        let _guard = self.write_lock.lock();
+        let meta = self.get_metadata(key)?;
+        let mut batch = Batch::new();
+        Self::batch_remove(&mut batch, key, &meta);
+        self.batch_totals(&mut batch, None, Some(&meta))?;
+        self.backend.apply_batch(batch)?;
+        Ok(meta)
+    }
+
+    /// Iterator over the keys of every entry tagged with `tag`
+    pub fn keys_with_tag(&self, tag: &str) -> impl Iterator<Item = Result<Vec<u8>>> + use<> {
+        let prefix_len = tag.len() + 1;
+        self.backend
+            .scan_prefix(Keyspace::Tags, &tag_prefix(tag))
+            .map(move |x| x.map(|(k, _)| k[prefix_len..].to_vec()))
+    }
+
+    /// Adds the operations that apply `access` to `meta` of the entry `key` to `batch`, updating
+    /// `meta` in place.
+    fn batch_access(
+        batch: &mut Batch,
+        key: &[u8],
+        meta: &mut Metadata,
+        access: PendingAccess,
+    ) -> Result<()> {
+        batch.remove(
+            Keyspace::AccessTime,
+            time_index_key(meta.last_accessed, key),
+        );
+        meta.apply_access(access);
+        batch.insert(Keyspace::Entries, key, meta.serialize()?);
+        batch.insert(
+            Keyspace::AccessTime,
+            time_index_key(meta.last_accessed, key),
+            Vec::new(),
+        );
+        Ok(())
     }
 
     /// Will increment the `hits` counter and set the `last_accessed` value to now for the found
     /// metadata key.
-    pub fn track_access_for(&self, key: &[u8], existing: Option<Metadata>) -> Result<Metadata> {
-        let mut meta = match existing {
-            Some(existing) => existing,
-            None => {
-                let entry = self
-                    .db
-                    .get(key)
-                    .map_err(ForcepError::MetaDb)?
-                    .ok_or(ForcepError::MetaNotFound)?;
-                Metadata::deserialize(&entry[..])?
-            }
-        };
-        meta.last_accessed = now_since_epoch();
-        meta.hits += 1;
-        self.db
-            .insert(key, Metadata::serialize(&meta))
-            .map_err(ForcepError::MetaDb)?;
+    ///
+    /// The stored entry is always re-read under the write lock, since the access time index has
+    /// to be updated from the exact timestamp that is currently stored.
+    pub fn track_access_for(&self, key: &[u8]) -> Result<Metadata> {
+        let _guard = self.write_lock.lock();
+        let mut meta = self.get_metadata(key)?;
+        let mut batch = Batch::new();
+        Self::batch_access(&mut batch, key, &mut meta, PendingAccess::now())?;
+        self.backend.apply_batch(batch)?;
         Ok(meta)
     }
 
+    /// Applies a set of buffered access tracking updates in a single batch.
+    ///
+    /// Updates for entries that no longer exist are skipped.
+    pub fn apply_accesses<'a, I>(&self, accesses: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a Vec<u8>, &'a PendingAccess)>,
+    {
+        let _guard = self.write_lock.lock();
+        let mut batch = Batch::new();
+        for (key, access) in accesses {
+            if let Some(mut meta) = self.get_metadata_opt(key)? {
+                Self::batch_access(&mut batch, key, &mut meta, *access)?;
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.backend.apply_batch(batch)
+    }
+
+    /// Retrieves the keys of (at most) the `n` oldest entries according to `index`, oldest
+    /// first.
+    ///
+    /// This only walks the first `n` records of the index, without deserializing any metadata.
+    pub fn oldest_keys(&self, index: TimeIndex, n: usize) -> Result<Vec<Vec<u8>>> {
+        self.keys_by_time(index)
+            .take(n)
+            .map(|x| x.map(|(_, k)| k))
+            .collect()
+    }
+
+    /// Iterator over the timestamp and key of every entry according to `index`, oldest first.
+    pub fn keys_by_time(
+        &self,
+        index: TimeIndex,
+    ) -> impl Iterator<Item = Result<(u64, Vec<u8>)>> + use<> {
+        self.backend.iter(index.keyspace()).map(|x| {
+            let (k, _) = x?;
+            let mut ts = [0; TIME_PREFIX_LEN];
+            ts.copy_from_slice(&k[..TIME_PREFIX_LEN]);
+            Ok((u64::from_be_bytes(ts), k[TIME_PREFIX_LEN..].to_vec()))
+        })
+    }
+
+    /// Iterator over the key of every entry, without deserializing any metadata
+    pub fn keys(&self) -> impl Iterator<Item = Result<Vec<u8>>> + use<> {
+        self.backend
+            .iter(Keyspace::Entries)
+            .map(|x| x.map(|(k, _)| k))
+    }
+
     /// Iterator over the entire metadata database
-    pub fn metadata_iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Metadata)>> {
-        self.db.iter().map(|x| match x {
-            Ok((key, data)) => Metadata::deserialize(&data[..]).map(|m| (key.to_vec(), m)),
-            Err(e) => Err(ForcepError::MetaDb(e)),
+    pub fn metadata_iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Metadata)>> + use<> {
+        self.backend.iter(Keyspace::Entries).map(|x| {
+            let (key, data) = x?;
+            Metadata::deserialize(&data).map(|m| (key, m))
         })
     }
 }
@@ -285,12 +1206,30 @@ impl MetaDb {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backends::SledBackend;
+    use std::path;
     const DATA: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
 
     fn create_db() -> Result<MetaDb> {
         const META_TESTDIR: &str = "./cache/test-index";
         let path = path::PathBuf::from(META_TESTDIR);
-        MetaDb::new(&path)
+        MetaDb::new(Arc::new(SledBackend::open(&path)?), HashAlgorithm::Md5)
+    }
+
+    /// A sled database shared by every test that uses it, since the same sled database can't be
+    /// opened by several tests at once
+    fn shared_db() -> &'static MetaDb {
+        static DB: std::sync::OnceLock<MetaDb> = std::sync::OnceLock::new();
+        DB.get_or_init(|| {
+            let backend = SledBackend::open("./cache/test-index-shared").unwrap();
+            MetaDb::new(Arc::new(backend), HashAlgorithm::Md5).unwrap()
+        })
+    }
+
+    /// A fresh database in memory, for tests that shouldn't share state with others
+    fn memory_db() -> MetaDb {
+        let backend = crate::backends::MemoryBackend::new();
+        MetaDb::new(Arc::new(backend), HashAlgorithm::Md5).unwrap()
     }
 
     #[test]
@@ -300,23 +1239,28 @@ mod test {
 
     #[test]
     fn db_read_write() {
-        let db = create_db().unwrap();
-        db.insert_metadata_for(&DATA, &DATA).unwrap();
+        let db = shared_db();
+        db.insert_metadata_for(&DATA, &DATA, Attributes::new(), vec![])
+            .unwrap();
         let meta = db.get_metadata(&DATA).unwrap();
         assert_eq!(meta.get_size(), DATA.len() as u64);
     }
 
     #[test]
     fn check_integrity() {
-        let db = create_db().unwrap();
-        let meta = db.insert_metadata_for(&DATA, &DATA).unwrap();
+        let db = shared_db();
+        let meta = db
+            .insert_metadata_for(&DATA, &DATA, Attributes::new(), vec![])
+            .unwrap();
         assert!(meta.check_integrity_of(&DATA));
     }
 
     #[test]
     fn last_modified() {
-        let db = create_db().unwrap();
-        let meta = db.insert_metadata_for(&DATA, &DATA).unwrap();
+        let db = shared_db();
+        let meta = db
+            .insert_metadata_for(&DATA, &DATA, Attributes::new(), vec![])
+            .unwrap();
         // make sure last-modified date is within last second
         assert_eq!(
             meta.get_last_modified()
@@ -330,10 +1274,201 @@ mod test {
 
     #[test]
     fn metadata_ser_de() {
-        let db = create_db().unwrap();
-        let meta = db.insert_metadata_for(&DATA, &DATA).unwrap();
-        let ser_bytes = meta.serialize();
+        let db = shared_db();
+        let meta = db
+            .insert_metadata_for(&DATA, &DATA, Attributes::new(), vec![])
+            .unwrap();
+        let ser_bytes = meta.serialize().unwrap();
         let de = Metadata::deserialize(&ser_bytes).unwrap();
-        assert_eq!(meta.get_integrity(), de.get_integrity());
+        assert_eq!(meta.get_integrity_digest(), de.get_integrity_digest());
+    }
+
+    #[test]
+    fn attributes_ser_de() {
+        let db = memory_db();
+        let mut attrs = Attributes::new();
+        attrs.insert("content-type".to_owned(), "text/plain".into());
+        attrs.insert("producer".to_owned(), 3i64.into());
+        attrs.insert("checked".to_owned(), true.into());
+        attrs.insert("origin".to_owned(), b"\x00\x01".as_slice().into());
+        let meta = db
+            .insert_metadata_for(&DATA, &DATA, attrs.clone(), vec![])
+            .unwrap();
+
+        let de = Metadata::deserialize(&meta.serialize().unwrap()).unwrap();
+        assert_eq!(de.get_attributes(), &attrs);
+    }
+
+    #[test]
+    fn update_attributes() {
+        let db = memory_db();
+        db.insert_metadata_for(b"ATTR_KEY", &DATA, Attributes::new(), vec![])
+            .unwrap();
+        db.update_attributes(b"ATTR_KEY", |attrs| {
+            attrs.insert("origin".to_owned(), "https://example.com".into());
+        })
+        .unwrap();
+
+        let meta = db.get_metadata(b"ATTR_KEY").unwrap();
+        assert_eq!(meta.get_size(), DATA.len() as u64);
+        assert_eq!(
+            meta.get_attribute("origin")
+                .and_then(AttributeValue::as_str),
+            Some("https://example.com")
+        );
+    }
+
+    #[test]
+    fn tag_index() {
+        let db = memory_db();
+        let tags = vec!["rev-1".to_owned(), "images".to_owned()];
+        let meta = db
+            .insert_metadata_for(b"TAG_KEY", &DATA, Attributes::new(), tags)
+            .unwrap();
+        assert_eq!(meta.get_tags(), ["images", "rev-1"]);
+        let keys: Vec<_> = db.keys_with_tag("rev-1").collect::<Result<_>>().unwrap();
+        assert_eq!(keys, vec![b"TAG_KEY".to_vec()]);
+
+        // overwriting the entry should drop the old tags from the index
+        db.insert_metadata_for(
+            b"TAG_KEY",
+            &DATA,
+            Attributes::new(),
+            vec!["rev-2".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(db.keys_with_tag("rev-1").count(), 0);
+        assert_eq!(db.keys_with_tag("rev-2").count(), 1);
+
+        db.remove_metadata_for(b"TAG_KEY").unwrap();
+        assert_eq!(db.keys_with_tag("rev-2").count(), 0);
+    }
+
+    #[test]
+    fn time_index() {
+        // the shared database is written to concurrently, so use a private one here
+        let backend = crate::backends::MemoryBackend::new();
+        let db = MetaDb::new(Arc::new(backend), HashAlgorithm::Md5).unwrap();
+        for key in [b"TIME_KEY1", b"TIME_KEY2", b"TIME_KEY3"] {
+            db.insert_metadata_for(key, &DATA, Attributes::new(), vec![])
+                .unwrap();
+            std::thread::sleep(time::Duration::from_millis(2));
+        }
+        let oldest = db.oldest_keys(TimeIndex::LastAccessed, 2).unwrap();
+        assert_eq!(oldest, vec![b"TIME_KEY1".to_vec(), b"TIME_KEY2".to_vec()]);
+
+        // accessing an entry should move it to the back of the access index only
+        db.track_access_for(b"TIME_KEY1").unwrap();
+        let oldest = db.oldest_keys(TimeIndex::LastAccessed, 3).unwrap();
+        assert_eq!(oldest[2], b"TIME_KEY1");
+        let oldest = db.oldest_keys(TimeIndex::LastModified, 3).unwrap();
+        assert_eq!(oldest[0], b"TIME_KEY1");
+
+        db.remove_metadata_for(b"TIME_KEY1").unwrap();
+        assert_eq!(db.oldest_keys(TimeIndex::LastAccessed, 3).unwrap().len(), 2);
+        assert_eq!(db.oldest_keys(TimeIndex::LastModified, 3).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn clone_eq() {
+        let mut attrs = Attributes::new();
+        attrs.insert("content-type".to_owned(), "text/plain".into());
+        let meta = Metadata::new(&DATA, HashAlgorithm::Md5, attrs, vec!["tag".to_owned()]);
+        let de = Metadata::deserialize(&meta.serialize().unwrap()).unwrap();
+        assert_eq!(meta.clone(), de);
+        let other = Metadata::new(b"other", HashAlgorithm::Md5, Attributes::new(), vec![]);
+        assert_ne!(meta, other);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json() {
+        let mut attrs = Attributes::new();
+        attrs.insert("producer".to_owned(), 3i64.into());
+        let meta = Metadata::new(&DATA, HashAlgorithm::Md5, attrs, vec!["tag".to_owned()]);
+        let json = serde_json::to_value(&meta).unwrap();
+        assert_eq!(json["integrity"], hex::encode(md5::compute(DATA).0));
+        assert_eq!(json["integrity_alg"], "md5");
+        assert_eq!(json["attributes"]["producer"]["int"], 3);
+        assert_eq!(serde_json::from_value::<Metadata>(json).unwrap(), meta);
+    }
+
+    #[test]
+    fn missing_optional_fields() {
+        use bson::{
+            cstr,
+            raw::{RawBinaryRef, RawBson, RawDocumentBuf},
+            spec::BinarySubtype,
+        };
+
+        // only the required fields (and one unknown one) are present
+        let mut doc = RawDocumentBuf::new();
+        doc.append(cstr!("size"), RawBson::Int64(DATA.len() as i64));
+        doc.append(
+            cstr!("integrity"),
+            RawBinaryRef {
+                subtype: BinarySubtype::Md5,
+                bytes: &md5::compute(DATA).0,
+            },
+        );
+        doc.append(cstr!("from_the_future"), RawBson::Boolean(true));
+
+        let meta = Metadata::deserialize(doc.as_bytes()).unwrap();
+        assert_eq!(meta.get_size(), DATA.len() as u64);
+        assert_eq!(meta.get_hits(), 0);
+        assert!(meta.get_last_modified().is_none());
+        assert!(meta.get_created_at().is_none());
+        assert_eq!(meta.get_version(), 1);
+        assert!(meta.check_integrity_of(&DATA));
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        use bson::{
+            cstr,
+            raw::{RawBinaryRef, RawBson, RawDocumentBuf},
+            spec::BinarySubtype,
+        };
+
+        // a record hashed with an algorithm of a newer version
+        let mut doc = RawDocumentBuf::new();
+        doc.append(cstr!("size"), RawBson::Int64(DATA.len() as i64));
+        doc.append(
+            cstr!("integrity"),
+            RawBinaryRef {
+                subtype: BinarySubtype::Generic,
+                bytes: &[1; 64],
+            },
+        );
+        doc.append(cstr!("integrity_alg"), "from_the_future");
+
+        let meta = Metadata::deserialize(doc.as_bytes()).unwrap();
+        assert_eq!(meta.get_size(), DATA.len() as u64);
+        assert_eq!(meta.get_integrity_algorithm(), HashAlgorithm::Unknown);
+        assert!(!meta.check_integrity_of(&DATA));
+        // the algorithm is written back unchanged
+        let bytes = meta.serialize().unwrap();
+        let doc = bson::RawDocument::from_bytes(&bytes).unwrap();
+        assert_eq!(doc.get_str("integrity_alg").unwrap(), "from_the_future");
+        assert_eq!(Metadata::deserialize(&bytes).unwrap(), meta);
+    }
+
+    #[test]
+    fn mixed_algorithms() {
+        // records with another algorithm must stay readable and verifiable
+        let mut meta = Metadata::new(&DATA, HashAlgorithm::Md5, Attributes::new(), vec![]);
+        #[allow(deprecated)]
+        let md5 = meta.get_integrity();
+        assert_eq!(md5, &md5::compute(DATA).0);
+        meta.integrity_alg = HashAlgorithm::Sha256;
+        meta.integrity = vec![0; 32];
+
+        let de = Metadata::deserialize(&meta.serialize().unwrap()).unwrap();
+        assert_eq!(de.get_integrity_algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(de.get_integrity_digest(), &[0; 32]);
+        #[allow(deprecated)]
+        let md5 = de.get_integrity();
+        assert_eq!(md5, &[0; 16]);
+        assert!(!de.check_integrity_of(&DATA));
     }
 }
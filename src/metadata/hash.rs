@@ -0,0 +1,184 @@
+/// The hash algorithm used to compute the integrity of cache entries.
+///
+/// The algorithm used for new entries is configured with
+/// [`CacheBuilder::integrity_algorithm`](crate::CacheBuilder::integrity_algorithm). Each entry
+/// stores the algorithm it was hashed with, so caches containing a mix of algorithms (for example
+/// after changing the configured algorithm) remain readable and verifiable.
+///
+/// Every algorithm except [`Md5`](Self::Md5) requires its matching cargo feature to be enabled:
+///
+/// | Algorithm                   | Feature  | Digest size |
+/// |-----------------------------|----------|-------------|
+/// | [`Md5`](Self::Md5)          | *none*   | 16 bytes    |
+/// | [`Xxh3`](Self::Xxh3)        | `xxh3`   | 16 bytes    |
+/// | [`Blake3`](Self::Blake3)    | `blake3` | 32 bytes    |
+/// | [`Sha256`](Self::Sha256)    | `sha256` | 32 bytes    |
+///
+/// # Examples
+///
+/// ```rust
+/// use forceps::HashAlgorithm;
+///
+/// assert!(HashAlgorithm::Md5.is_available());
+/// assert_eq!(HashAlgorithm::default(), HashAlgorithm::Md5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum HashAlgorithm {
+    /// The `md5` message digest. Fast enough for most workloads, but not cryptographically secure.
+    #[default]
+    Md5,
+    /// The 128-bit variant of `xxh3`, a very fast non-cryptographic hash.
+    Xxh3,
+    /// The `blake3` cryptographic hash.
+    Blake3,
+    /// The `sha256` cryptographic hash.
+    Sha256,
+    /// An algorithm this version of forceps doesn't know, found on entries written by a newer
+    /// version. It's never available, so these entries can be read but not verified.
+    Unknown,
+}
+
+impl HashAlgorithm {
+    /// Whether support for this algorithm has been compiled in (see the [`HashAlgorithm`]
+    /// documentation for the required features).
+    #[inline]
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Md5 => true,
+            Self::Xxh3 => cfg!(feature = "xxh3"),
+            Self::Blake3 => cfg!(feature = "blake3"),
+            Self::Sha256 => cfg!(feature = "sha256"),
+            Self::Unknown => false,
+        }
+    }
+
+    /// The identifier of this algorithm that is persisted in the metadata database.
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Xxh3 => "xxh3",
+            Self::Blake3 => "blake3",
+            Self::Sha256 => "sha256",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// Parses a persisted algorithm identifier created by [`id`](Self::id).
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "md5" => Some(Self::Md5),
+            "xxh3" => Some(Self::Xxh3),
+            "blake3" => Some(Self::Blake3),
+            "sha256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Computes the digest of `data`, or `None` if the algorithm [is not
+    /// available](Self::is_available).
+    pub(crate) fn digest(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::Md5 => Some(md5::compute(data).0.to_vec()),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => Some(xxhash_rust::xxh3::xxh3_128(data).to_be_bytes().to_vec()),
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => Some(blake3::hash(data).as_bytes().to_vec()),
+            #[cfg(feature = "sha256")]
+            Self::Sha256 => {
+                use sha2::Digest;
+                Some(sha2::Sha256::digest(data).to_vec())
+            }
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Creates a [`Hasher`] computing the digest of data fed to it incrementally, or `None` if
+    /// the algorithm [is not available](Self::is_available).
+    pub(crate) fn hasher(self) -> Option<Hasher> {
+        match self {
+            Self::Md5 => Some(Hasher::Md5(md5::Context::new())),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => Some(Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new()))),
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => Some(Hasher::Blake3(Box::new(blake3::Hasher::new()))),
+            #[cfg(feature = "sha256")]
+            Self::Sha256 => {
+                use sha2::Digest;
+                Some(Hasher::Sha256(sha2::Sha256::new()))
+            }
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+/// Incremental version of [`HashAlgorithm::digest`], so large entries can be hashed without
+/// reading them into memory at once.
+pub(crate) enum Hasher {
+    Md5(md5::Context),
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+    #[cfg(feature = "sha256")]
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    /// Feeds the next chunk of `data` to the hasher
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(ctx) => ctx.consume(data),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(hasher) => hasher.update(data),
+            #[cfg(feature = "blake3")]
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            #[cfg(feature = "sha256")]
+            Self::Sha256(hasher) => sha2::Digest::update(hasher, data),
+        }
+    }
+
+    /// The digest of all the data fed to the hasher, the same as [`HashAlgorithm::digest`]
+    /// would have computed
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Md5(ctx) => ctx.finalize().0.to_vec(),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(hasher) => hasher.digest128().to_be_bytes().to_vec(),
+            #[cfg(feature = "blake3")]
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            #[cfg(feature = "sha256")]
+            Self::Sha256(hasher) => sha2::Digest::finalize(hasher).to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn id_roundtrip() {
+        for alg in [
+            HashAlgorithm::Md5,
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Sha256,
+        ] {
+            assert_eq!(HashAlgorithm::from_id(alg.id()), Some(alg));
+            assert_eq!(alg.digest(b"Hello World").is_some(), alg.is_available());
+            if let Some(mut hasher) = alg.hasher() {
+                hasher.update(b"Hello ");
+                hasher.update(b"World");
+                assert_eq!(Some(hasher.finalize()), alg.digest(b"Hello World"));
+            }
+        }
+        assert!(!HashAlgorithm::Unknown.is_available());
+        assert_eq!(HashAlgorithm::from_id(HashAlgorithm::Unknown.id()), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! Intent journal for operations that change the file and the metadata of an entry separately
+//!
+//! Entry files and their metadata live in two different stores, so writes and removals can't
+//! change both atomically. Before such an operation touches the file system, an [`Intent`]
+//! describing it is recorded in the `journal` keyspace, and it's removed again once the metadata
+//! has been updated as well. Intents that are still in the journal when a [`Cache`] is built
+//! belong to interrupted operations, which are then either completed or undone depending on what
+//! made it to disk.
+//!
+//! [`Cache`]: crate::Cache
+
+use super::{MetaDb, Metadata};
+use crate::backends::{Keyspace, MetaBackend};
+use crate::{ForcepError, Result};
+use std::sync::atomic::Ordering;
+
+/// An operation that changes the file and the metadata of an entry, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Intent {
+    /// The staged temporary file `tmp` (in the cache directory) is moved to the entry `key`,
+    /// after which the metadata of the entry is replaced with `meta`
+    Write {
+        key: Vec<u8>,
+        tmp: String,
+        meta: Metadata,
+    },
+    /// The file of the entry `key` is moved to the temporary file `tmp` (in the cache directory)
+    /// and deleted, after which the metadata of the entry is removed
+    Remove { key: Vec<u8>, tmp: String },
+    /// The file of the entry `key` is moved to the quarantined entry `id`, whose record was
+    /// written beforehand, after which the metadata of the entry is removed
+    Quarantine { key: Vec<u8>, id: String },
+    /// Every change of a transaction is moved into place, after which the metadata of every
+    /// entry is updated in a single batch that also ends the intent
+    Transaction { changes: Vec<TxChange> },
+}
+
+/// The file system change of a single operation in an [`Intent::Transaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TxChange {
+    /// The entry that is changed
+    pub key: Vec<u8>,
+    /// The staged temporary file (in the cache directory) that is moved to the entry, or `None`
+    /// for removals
+    pub tmp: Option<String>,
+    /// The temporary file (in the cache directory) the current file of the entry is moved to,
+    /// until the transaction can't be undone anymore
+    pub backup: String,
+}
+
+impl Intent {
+    /// Serializes the intent into bytes
+    fn serialize(&self) -> Result<Vec<u8>> {
+        use bson::{
+            cstr,
+            raw::{RawArrayBuf, RawBinaryRef, RawBson, RawDocumentBuf},
+            spec::BinarySubtype,
+        };
+        let binary = |bytes| RawBinaryRef {
+            subtype: BinarySubtype::Generic,
+            bytes,
+        };
+
+        let mut doc = RawDocumentBuf::new();
+        match self {
+            Self::Write { key, tmp, meta } => {
+                doc.append(cstr!("op"), "write");
+                doc.append(cstr!("key"), binary(key));
+                doc.append(cstr!("tmp"), tmp.as_str());
+                doc.append(cstr!("meta"), binary(&meta.serialize()?));
+            }
+            Self::Remove { key, tmp } => {
+                doc.append(cstr!("op"), "remove");
+                doc.append(cstr!("key"), binary(key));
+                doc.append(cstr!("tmp"), tmp.as_str());
+            }
+            Self::Quarantine { key, id } => {
+                doc.append(cstr!("op"), "quarantine");
+                doc.append(cstr!("key"), binary(key));
+                doc.append(cstr!("id"), id.as_str());
+            }
+            Self::Transaction { changes } => {
+                let mut array = RawArrayBuf::new();
+                for change in changes {
+                    let mut change_doc = RawDocumentBuf::new();
+                    change_doc.append(cstr!("key"), binary(&change.key));
+                    if let Some(tmp) = &change.tmp {
+                        change_doc.append(cstr!("tmp"), tmp.as_str());
+                    }
+                    change_doc.append(cstr!("backup"), change.backup.as_str());
+                    array.push(RawBson::Document(change_doc));
+                }
+                doc.append(cstr!("op"), "transaction");
+                doc.append(cstr!("changes"), RawBson::Array(array));
+            }
+        }
+        Ok(doc.into_bytes())
+    }
+
+    /// Deserializes a slice of bytes into an intent
+    fn deserialize(buf: &[u8]) -> Result<Self> {
+        use bson::raw::RawDocument;
+
+        let doc = RawDocument::from_bytes(buf).map_err(ForcepError::MetaDe)?;
+        match doc.get_str("op").map_err(ForcepError::MetaDe)? {
+            "write" => {
+                let meta = doc.get_binary("meta").map_err(ForcepError::MetaDe)?;
+                let meta = Metadata::deserialize(meta.bytes)?;
+                Ok(Self::Write {
+                    key: get_key(doc)?,
+                    tmp: get_string(doc, "tmp")?,
+                    meta,
+                })
+            }
+            "remove" => Ok(Self::Remove {
+                key: get_key(doc)?,
+                tmp: get_string(doc, "tmp")?,
+            }),
+            "quarantine" => Ok(Self::Quarantine {
+                key: get_key(doc)?,
+                id: get_string(doc, "id")?,
+            }),
+            "transaction" => {
+                let mut changes = Vec::new();
+                for change in doc.get_array("changes").map_err(ForcepError::MetaDe)? {
+                    let change = change.map_err(ForcepError::MetaDe)?;
+                    let Some(change) = change.as_document() else {
+                        return Err(invalid_data(
+                            "changes",
+                            "transaction change isn't a document",
+                        ));
+                    };
+                    // removals don't have a staged file
+                    let tmp = match change.get("tmp").map_err(ForcepError::MetaDe)? {
+                        Some(_) => Some(get_string(change, "tmp")?),
+                        None => None,
+                    };
+                    changes.push(TxChange {
+                        key: get_key(change)?,
+                        tmp,
+                        backup: get_string(change, "backup")?,
+                    });
+                }
+                Ok(Self::Transaction { changes })
+            }
+            _ => Err(invalid_data("op", "unknown journal operation")),
+        }
+    }
+}
+
+/// Reads the binary `key` field of `doc`
+fn get_key(doc: &bson::raw::RawDocument) -> Result<Vec<u8>> {
+    doc.get_binary("key")
+        .map(|key| key.bytes.to_vec())
+        .map_err(ForcepError::MetaDe)
+}
+
+/// Reads the string field `name` of `doc`
+fn get_string(doc: &bson::raw::RawDocument, name: &str) -> Result<String> {
+    doc.get_str(name)
+        .map(str::to_owned)
+        .map_err(ForcepError::MetaDe)
+}
+
+/// A deserialization error for the field `key` with the message `msg`
+fn invalid_data(key: &str, msg: &str) -> ForcepError {
+    let io_err = std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned());
+    let mut err = bson::error::Error::from(io_err);
+    err.key = Some(key.to_owned());
+    ForcepError::MetaDe(err)
+}
+
+/// The id that the next intent recorded in the journal of `backend` should get. Keys that aren't
+/// ids are reported by [`MetaDb::intents`] instead, so they can't keep the database from opening.
+pub(super) fn next_id(backend: &dyn MetaBackend) -> Result<u64> {
+    let mut next = 0;
+    for x in backend.iter(Keyspace::Journal) {
+        let (id, _) = x?;
+        if let Ok(id) = <[u8; 8]>::try_from(&id[..]) {
+            next = next.max(u64::from_be_bytes(id) + 1);
+        }
+    }
+    Ok(next)
+}
+
+impl MetaDb {
+    /// Records `intent` in the journal, returning the id to end it with.
+    pub fn begin_intent(&self, intent: &Intent) -> Result<u64> {
+        let id = self.next_intent.fetch_add(1, Ordering::Relaxed);
+        self.backend
+            .insert(Keyspace::Journal, &id.to_be_bytes(), &intent.serialize()?)?;
+        Ok(id)
+    }
+
+    /// Removes the intent `id` from the journal, once its operation has been completed or undone.
+    #[inline]
+    pub fn end_intent(&self, id: u64) -> Result<()> {
+        self.backend.remove(Keyspace::Journal, &id.to_be_bytes())
+    }
+
+    /// Every intent that is still in the journal along with its id, oldest first. Intents that
+    /// can't be deserialized are `None`, so they can still be ended.
+    pub fn intents(&self) -> Result<Vec<(u64, Option<Intent>)>> {
+        self.backend
+            .iter(Keyspace::Journal)
+            .map(|x| {
+                let (id, data) = x?;
+                let id: [u8; 8] = id[..]
+                    .try_into()
+                    .map_err(|_| invalid_data("journal", "intent ids must be 8 bytes"))?;
+                Ok((u64::from_be_bytes(id), Intent::deserialize(&data).ok()))
+            })
+            .collect()
+    }
+
+    /// Completes an interrupted write whose file was already moved into place, by inserting
+    /// `meta` for the entry `key` unless its metadata already describes the same data.
+    ///
+    /// Returns whether the metadata was inserted.
+    pub fn complete_write(&self, key: &[u8], meta: Metadata) -> Result<bool> {
+        if let Some(current) = self.get_metadata_opt(key)?
+            && current.size == meta.size
+            && current.integrity_alg == meta.integrity_alg
+            && current.integrity == meta.integrity
+        {
+            return Ok(false);
+        }
+        self.insert_metadata_with(key, meta, None)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backends::MemoryBackend;
+    use crate::{Attributes, HashAlgorithm};
+    use std::sync::Arc;
+
+    #[test]
+    fn journal() {
+        let db = MetaDb::new(Arc::new(MemoryBackend::new()), HashAlgorithm::Md5).unwrap();
+        let write = Intent::Write {
+            key: b"KEY".to_vec(),
+            tmp: "tmp0123456789".to_owned(),
+            meta: Metadata::new(b"DATA", HashAlgorithm::Md5, Attributes::new(), vec![]),
+        };
+        let remove = Intent::Remove {
+            key: b"KEY".to_vec(),
+            tmp: "tmpabcdefghij".to_owned(),
+        };
+        let quarantine = Intent::Quarantine {
+            key: b"KEY".to_vec(),
+            id: "0123456789abcdef".to_owned(),
+        };
+        let first = db.begin_intent(&write).unwrap();
+        let second = db.begin_intent(&remove).unwrap();
+        let third = db.begin_intent(&quarantine).unwrap();
+        assert!(first < second && second < third);
+        assert_eq!(
+            db.intents().unwrap(),
+            vec![
+                (first, Some(write)),
+                (second, Some(remove)),
+                (third, Some(quarantine))
+            ]
+        );
+
+        db.end_intent(first).unwrap();
+        assert_eq!(db.intents().unwrap().len(), 2);
+        assert_eq!(next_id(db.backend.as_ref()).unwrap(), third + 1);
+    }
+
+    #[test]
+    fn malformed_key() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.insert(Keyspace::Journal, b"BAD", b"").unwrap();
+        backend
+            .insert(Keyspace::Journal, &5u64.to_be_bytes(), b"")
+            .unwrap();
+        let db = MetaDb::new(backend, HashAlgorithm::Md5).unwrap();
+        assert_eq!(next_id(db.backend.as_ref()).unwrap(), 6);
+        assert!(matches!(db.intents(), Err(ForcepError::MetaDe(_))));
+    }
+}
@@ -0,0 +1,182 @@
+//! Format versioning and on-open migrations for the metadata database
+//!
+//! Every metadata database stores its format version in the `info` tree. When a [`Cache`] is
+//! built, [`migrate`] compares that version against [`CURRENT_VERSION`] and runs each migration
+//! in order until the database is up to date. The version record is updated after every step, so
+//! an interrupted migration will simply resume from where it left off.
+//!
+//! Adding a new format version only requires bumping [`CURRENT_VERSION`] and appending a
+//! [`Migration`] that upgrades from the previous version to [`MIGRATIONS`].
+//!
+//! [`Cache`]: crate::Cache
+
+use super::{MetaDb, Metadata};
+use crate::backends::Keyspace;
+use crate::{ForcepError, Result};
+
+/// The format version that this version of `forceps` reads and writes.
+pub(crate) const CURRENT_VERSION: u32 = 5;
+
+/// The format version of databases created before the version record was introduced.
+const UNVERSIONED: u32 = 1;
+
+/// A single step that upgrades a database from format version `from` to `from + 1`.
+struct Migration {
+    from: u32,
+    run: fn(&MetaDb) -> Result<()>,
+}
+
+/// All migrations, ordered by the version they upgrade from.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        run: v1_add_attributes_and_tags,
+    },
+    Migration {
+        from: 2,
+        run: v2_integrity_algorithms,
+    },
+    Migration {
+        from: 3,
+        run: v3_time_indexes,
+    },
+    Migration {
+        from: 4,
+        run: v4_totals,
+    },
+];
+
+/// The format version of the database, treating brand new databases as up to date
+fn version(db: &MetaDb) -> Result<u32> {
+    Ok(match db.format_version()? {
+        Some(v) => v,
+        // a brand new database doesn't need any migrations
+        None if db.backend.is_empty(Keyspace::Entries)? => CURRENT_VERSION,
+        None => UNVERSIONED,
+    })
+}
+
+/// Whether the database is at [`CURRENT_VERSION`], so it can be used without migrating it.
+pub(super) fn is_current(db: &MetaDb) -> Result<bool> {
+    Ok(version(db)? == CURRENT_VERSION)
+}
+
+/// Upgrades the database to [`CURRENT_VERSION`], running every required migration.
+pub(super) fn migrate(db: &MetaDb) -> Result<()> {
+    let version = version(db)?;
+    if version > CURRENT_VERSION {
+        return Err(ForcepError::MetaVersion(Some(version)));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        (migration.run)(db)?;
+        db.set_format_version(migration.from + 1)?;
+    }
+    db.set_format_version(CURRENT_VERSION)
+}
+
+/// Version 2 added user-defined attributes and tags to every record. Records are rewritten so
+/// they contain the (empty) fields.
+fn v1_add_attributes_and_tags(db: &MetaDb) -> Result<()> {
+    for result in db.backend.iter(Keyspace::Entries) {
+        let (key, data) = result?;
+        let meta = Metadata::deserialize(&data)?;
+        db.backend
+            .insert(Keyspace::Entries, &key, &meta.serialize()?)?;
+    }
+    Ok(())
+}
+
+/// Version 3 made the integrity algorithm configurable. Records without an algorithm identifier
+/// are read as `md5`, so nothing has to be rewritten, but older versions can't read records hashed
+/// with anything else.
+fn v2_integrity_algorithms(_db: &MetaDb) -> Result<()> {
+    Ok(())
+}
+
+/// Version 4 added the access and modification time indexes used for eviction. They are built
+/// from the existing records.
+fn v3_time_indexes(db: &MetaDb) -> Result<()> {
+    for result in db.metadata_iter() {
+        let (key, meta) = result?;
+        db.index_times(&key, &meta)?;
+    }
+    Ok(())
+}
+
+/// Version 5 added the total size and entry count counters, which are computed from the existing
+/// records.
+fn v4_totals(db: &MetaDb) -> Result<()> {
+    let (mut size, mut count) = (0, 0);
+    for result in db.metadata_iter() {
+        let (_, meta) = result?;
+        size += meta.get_size();
+        count += 1;
+    }
+    db.set_totals(size, count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::HashAlgorithm;
+    use crate::backends::SledBackend;
+    use std::sync::Arc;
+
+    fn open_db(path: &str) -> MetaDb {
+        let backend = SledBackend::open(path).unwrap();
+        MetaDb::new(Arc::new(backend), HashAlgorithm::Md5).unwrap()
+    }
+
+    #[test]
+    fn unversioned_upgrade() {
+        use bson::{
+            cstr,
+            raw::{RawBinaryRef, RawBson, RawDocumentBuf},
+            spec::BinarySubtype,
+        };
+        const DATA: &[u8] = b"Hello World";
+
+        let db = open_db("./cache/test-index-migrations");
+
+        // simulate a record written by the original format, without a version record
+        let mut doc = RawDocumentBuf::new();
+        doc.append(cstr!("size"), RawBson::Int64(DATA.len() as i64));
+        doc.append(cstr!("last_modified"), RawBson::Int64(1));
+        doc.append(cstr!("last_accessed"), RawBson::Int64(1));
+        doc.append(cstr!("hits"), RawBson::Int64(0));
+        doc.append(
+            cstr!("integrity"),
+            RawBinaryRef {
+                subtype: BinarySubtype::Md5,
+                bytes: &md5::compute(DATA).0,
+            },
+        );
+        db.backend
+            .insert(Keyspace::Entries, b"OLD_KEY", doc.as_bytes())
+            .unwrap();
+        db.backend
+            .remove(Keyspace::Info, super::super::VERSION_KEY)
+            .unwrap();
+
+        db.migrate().unwrap();
+        assert_eq!(db.format_version().unwrap(), Some(CURRENT_VERSION));
+        let meta = db.get_metadata(b"OLD_KEY").unwrap();
+        assert!(meta.get_attributes().is_empty());
+        assert!(meta.check_integrity_of(DATA));
+        let oldest = db.oldest_keys(crate::TimeIndex::LastModified, 1).unwrap();
+        assert_eq!(oldest, vec![b"OLD_KEY".to_vec()]);
+        assert_eq!(db.entry_count().unwrap(), 1);
+        assert_eq!(db.total_size().unwrap(), DATA.len() as u64);
+    }
+
+    #[test]
+    fn newer_version_rejected() {
+        let db = open_db("./cache/test-index-newer");
+        db.set_format_version(CURRENT_VERSION + 1).unwrap();
+        assert!(matches!(
+            db.migrate(),
+            Err(ForcepError::MetaVersion(Some(v))) if v == CURRENT_VERSION + 1
+        ));
+    }
+}
@@ -13,11 +13,36 @@ fn tmpname(prefix: &str, rand_len: usize) -> String {
     buf
 }
 
+/// Prefix of every temporary file name
+const PREFIX: &str = "tmp";
+/// Number of random characters after the prefix
+const LEN: usize = 10;
+
 /// Creates a randomized path in a directory that can be used as a temporary file
 pub(crate) fn tmppath_in(dir: &path::Path) -> path::PathBuf {
-    const LEN: usize = 10;
     let mut buf = path::PathBuf::new();
     buf.push(dir);
-    buf.push(tmpname("tmp", LEN));
+    buf.push(tmpname(PREFIX, LEN));
     buf
 }
+
+/// Whether `name` is a file name that could have been created by [`tmppath_in`]
+pub(crate) fn is_tmpname(name: &str) -> bool {
+    name.strip_prefix(PREFIX)
+        .is_some_and(|rest| rest.len() == LEN && rest.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tmpname_detection() {
+        let path = tmppath_in(path::Path::new("./cache"));
+        assert!(is_tmpname(path.file_name().unwrap().to_str().unwrap()));
+        assert!(!is_tmpname("index"));
+        assert!(!is_tmpname("tmp"));
+        assert!(!is_tmpname("tmpABCDEFGHIJK"));
+        assert!(!is_tmpname("tmpABCDE-GHIJ"));
+    }
+}